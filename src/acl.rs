@@ -0,0 +1,205 @@
+use context::Identity;
+use serde_json;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use toml;
+
+/// 没有规则匹配时的处理方式
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    Allow,
+    #[default]
+    Deny,
+}
+
+/// 一条规则：`namespace` 和 `function` 可以以 `*` 结尾匹配前缀，`namespace` 默认匹配所有服务
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Rule {
+    #[serde(default = "any")]
+    pub namespace: String,
+    pub function: String,
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+fn any() -> String {
+    String::from("*")
+}
+
+#[derive(Deserialize)]
+struct PolicyFile {
+    #[serde(default)]
+    default: Decision,
+    #[serde(default)]
+    roles: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    rules: Vec<Rule>,
+}
+
+/// 声明式的访问策略，在服务端执行函数之前检查，不满足时返回 `Error::PermissionDenied`
+///
+/// 规则中的调用方可以是身份名称（CN 或 SAN）、`role:<角色>` 或表示任何调用方的 `*`，
+/// 角色在 `[roles]` 中列出成员。匹配函数的规则中任一条拒绝调用方则拒绝，否则需要有一条允许；
+/// 没有规则匹配时按 `default` 处理，默认拒绝。
+///
+/// ```toml
+/// default = "deny"
+///
+/// [roles]
+/// admin = ["admin.example.com"]
+/// reader = ["path_client", "dashboard"]
+///
+/// [[rules]]
+/// namespace = "path_server"
+/// function = "find_*"
+/// allow = ["role:reader", "role:admin"]
+///
+/// [[rules]]
+/// function = "update_graph"
+/// allow = ["role:admin"]
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessPolicy {
+    namespace: String,
+    default: Decision,
+    roles: HashMap<String, Vec<String>>,
+    rules: Vec<Rule>,
+}
+
+impl AccessPolicy {
+    /// 读取 TOML 或 JSON 文件（按扩展名区分），只使用适用于 `namespace` 的规则
+    pub fn from_file<P: AsRef<Path>>(path: P, namespace: &str) -> io::Result<Self> {
+        let content = fs::read_to_string(&path)?;
+        let file: PolicyFile = if path.as_ref().extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&content).map_err(invalid_data)?
+        } else {
+            toml::from_str(&content).map_err(invalid_data)?
+        };
+        Ok(Self {
+            namespace: String::from(namespace),
+            default: file.default,
+            roles: file.roles,
+            rules: file.rules.into_iter().filter(|rule| matches(&rule.namespace, namespace)).collect(),
+        })
+    }
+
+    pub fn new(namespace: &str, default: Decision) -> Self {
+        Self {
+            namespace: String::from(namespace),
+            default,
+            roles: HashMap::new(),
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn role(mut self, role: &str, members: Vec<String>) -> Self {
+        self.roles.insert(String::from(role), members);
+        self
+    }
+
+    /// 不适用于该服务的规则被忽略
+    pub fn rule(mut self, rule: Rule) -> Self {
+        if matches(&rule.namespace, &self.namespace) {
+            self.rules.push(rule);
+        }
+        self
+    }
+
+    /// 没有身份的调用方只匹配 `*`
+    pub fn is_allowed(&self, identity: Option<&Identity>, function: &str) -> bool {
+        let rules: Vec<&Rule> = self.rules.iter().filter(|rule| matches(&rule.function, function)).collect();
+        if rules.is_empty() {
+            return self.default == Decision::Allow;
+        }
+        if rules.iter().any(|rule| rule.deny.iter().any(|principal| self.is_principal(identity, principal))) {
+            return false;
+        }
+        rules.iter().any(|rule| rule.allow.iter().any(|principal| self.is_principal(identity, principal)))
+    }
+
+    fn is_principal(&self, identity: Option<&Identity>, principal: &str) -> bool {
+        if principal == "*" {
+            return true;
+        }
+        let identity = match identity {
+            Some(identity) => identity,
+            None => return false,
+        };
+        match principal.strip_prefix("role:") {
+            Some(role) => self.roles.get(role).is_some_and(|members| members.iter().any(|member| identity.has_name(member))),
+            None => identity.has_name(principal),
+        }
+    }
+}
+
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
+    }
+}
+
+fn invalid_data<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use acl::{AccessPolicy, Decision, Rule};
+    use context::Identity;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn policy_file_test() {
+        let path = env::temp_dir().join("myrpc4rs_policy_file_test.toml");
+        fs::write(&path, r#"
+[roles]
+admin = ["admin.example.com"]
+reader = ["path_client"]
+
+[[rules]]
+namespace = "path_server"
+function = "find_*"
+allow = ["role:reader", "role:admin"]
+
+[[rules]]
+function = "update_graph"
+allow = ["role:admin"]
+
+[[rules]]
+namespace = "other"
+function = "*"
+allow = ["*"]
+"#).unwrap();
+        let policy = AccessPolicy::from_file(&path, "path_server").unwrap();
+        let admin = Identity::new("CN=admin", vec![String::from("admin.example.com")]);
+        let reader = Identity::named("path_client");
+        assert!(policy.is_allowed(Some(&reader), "find_shortest_path"));
+        assert!(!policy.is_allowed(Some(&reader), "update_graph"));
+        assert!(policy.is_allowed(Some(&admin), "update_graph"));
+        assert!(!policy.is_allowed(None, "find_shortest_path"));
+        // 没有规则匹配时默认拒绝
+        assert!(!policy.is_allowed(Some(&admin), "shutdown"));
+        assert!(AccessPolicy::from_file(&path, "other").unwrap().is_allowed(None, "shutdown"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn deny_test() {
+        let policy = AccessPolicy::new("path_server", Decision::Allow)
+            .rule(Rule {
+                namespace: String::from("*"),
+                function: String::from("*"),
+                allow: vec![String::from("*")],
+                deny: vec![String::from("mallory")],
+            });
+        assert!(policy.is_allowed(None, "find_shortest_path"));
+        assert!(!policy.is_allowed(Some(&Identity::named("mallory")), "find_shortest_path"));
+    }
+}
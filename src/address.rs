@@ -0,0 +1,101 @@
+use std::error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// 服务端监听或客户端连接的地址：TCP 地址、Unix 域套接字路径或进程内服务端的名字
+///
+/// 从字符串解析时，`unix:` 前缀或包含 `/` 的字符串视为 Unix 域套接字路径，
+/// `memory:` 前缀表示同一进程内的服务端（不经过套接字，用于测试和嵌入）：
+///
+/// ```
+/// use myrpc4rs::address::Address;
+///
+/// let tcp: Address = "127.0.0.1:6181".parse().unwrap();
+/// let unix: Address = "unix:/run/path_server.sock".parse().unwrap();
+/// assert_eq!(Address::Unix("/run/path_server.sock".into()), unix);
+/// assert_eq!(unix, "/run/path_server.sock".parse().unwrap());
+/// assert_eq!("127.0.0.1:6181", tcp.to_string());
+/// assert_eq!(Address::Memory("path_server".into()), "memory:path_server".parse().unwrap());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    Memory(String),
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Address::Tcp(ref addr) => write!(f, "{}", addr),
+            Address::Unix(ref path) => write!(f, "unix:{}", path.display()),
+            Address::Memory(ref name) => write!(f, "memory:{}", name),
+        }
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(addr: SocketAddr) -> Self {
+        Address::Tcp(addr)
+    }
+}
+
+impl From<PathBuf> for Address {
+    fn from(path: PathBuf) -> Self {
+        Address::Unix(path)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddressParseError(String);
+
+impl fmt::Display for AddressParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid address: {}", self.0)
+    }
+}
+
+impl error::Error for AddressParseError {}
+
+impl FromStr for Address {
+    type Err = AddressParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(AddressParseError(String::from(s)));
+            }
+            return Ok(Address::Unix(PathBuf::from(path)));
+        }
+        if let Some(name) = s.strip_prefix("memory:") {
+            if name.is_empty() {
+                return Err(AddressParseError(String::from(s)));
+            }
+            return Ok(Address::Memory(String::from(name)));
+        }
+        if s.contains('/') {
+            return Ok(Address::Unix(PathBuf::from(s)));
+        }
+        s.parse().map(Address::Tcp).map_err(|_| AddressParseError(String::from(s)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use address::Address;
+    use std::path::PathBuf;
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(Ok(Address::Tcp("127.0.0.1:6181".parse().unwrap())), "127.0.0.1:6181".parse());
+        assert_eq!(Ok(Address::Unix(PathBuf::from("./path.sock"))), "./path.sock".parse());
+        assert_eq!(Ok(Address::Unix(PathBuf::from("path.sock"))), "unix:path.sock".parse());
+        assert!("unix:".parse::<Address>().is_err());
+        assert_eq!(Ok(Address::Memory(String::from("test"))), "memory:test".parse());
+        assert!("memory:".parse::<Address>().is_err());
+        assert!("localhost".parse::<Address>().is_err());
+        assert_eq!("unix:/tmp/path.sock", Address::Unix(PathBuf::from("/tmp/path.sock")).to_string());
+    }
+}
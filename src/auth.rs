@@ -0,0 +1,180 @@
+use context::{Identity, RequestContext};
+use error::{Error, Result};
+use hex;
+use hmac_sha256::HMAC;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 请求头，客户端随请求发送的元数据
+pub type Headers = HashMap<String, String>;
+
+/// 凭据所在的请求头，值为 `Bearer <token>`
+pub const AUTHORIZATION: &str = "authorization";
+
+/// 服务端在执行函数之前校验请求的凭据，通过 `MyRPCServer::authenticator` 设置。
+/// 返回的身份替换 `RequestContext` 中的身份，访问规则据此判断；失败时返回 `Error::Unauthenticated`。
+pub trait Authenticator {
+    fn authenticate(&self, context: &RequestContext, function: &str, headers: &Headers) -> Result<Identity>;
+}
+
+/// 客户端在发送每个请求（包括重试）之前调用，返回的请求头附加到请求中
+pub trait CredentialProvider {
+    fn headers(&self, function: &str) -> Headers;
+}
+
+/// 取出 `authorization` 头中的 bearer token
+pub fn bearer_token(headers: &Headers) -> Option<&str> {
+    headers.get(AUTHORIZATION).and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// 预先分配的静态 token，每个 token 对应一个调用方名称
+///
+/// ```
+/// use myrpc4rs::auth::StaticTokenAuthenticator;
+///
+/// let authenticator = StaticTokenAuthenticator::new()
+///     .token("s3cr3t", "path_client")
+///     .token("d4shb0ard", "dashboard");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StaticTokenAuthenticator {
+    tokens: HashMap<String, String>,
+}
+
+impl StaticTokenAuthenticator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn token(mut self, token: &str, name: &str) -> Self {
+        self.tokens.insert(String::from(token), String::from(name));
+        self
+    }
+}
+
+impl Authenticator for StaticTokenAuthenticator {
+    fn authenticate(&self, _: &RequestContext, _: &str, headers: &Headers) -> Result<Identity> {
+        let token = bearer_token(headers).ok_or(Error::Unauthenticated)?;
+        self.tokens.iter()
+            .find(|(known, _)| constant_time_eq(known.as_bytes(), token.as_bytes()))
+            .map(|(_, name)| Identity::named(name))
+            .ok_or(Error::Unauthenticated)
+    }
+}
+
+/// HMAC-SHA256 签名的 token：`<名称>.<过期时间>.<签名>`，过期时间为 Unix 秒数，
+/// 签名是用共享密钥对 `<名称>.<过期时间>` 计算的 HMAC 的十六进制表示
+pub struct HmacAuthenticator {
+    secret: Vec<u8>,
+}
+
+impl HmacAuthenticator {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+        }
+    }
+}
+
+impl Authenticator for HmacAuthenticator {
+    fn authenticate(&self, _: &RequestContext, _: &str, headers: &Headers) -> Result<Identity> {
+        let token = bearer_token(headers).ok_or(Error::Unauthenticated)?;
+        let mut parts = token.rsplitn(3, '.');
+        let (signature, expires, name) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(signature), Some(expires), Some(name)) => (signature, expires, name),
+            _ => return Err(Error::Unauthenticated),
+        };
+        let expected = hex::encode(HMAC::mac(format!("{}.{}", name, expires).as_bytes(), &self.secret));
+        if !constant_time_eq(expected.as_bytes(), signature.as_bytes()) {
+            return Err(Error::Unauthenticated);
+        }
+        match expires.parse::<u64>() {
+            Ok(expires) if expires > unix_time() => Ok(Identity::named(name)),
+            _ => Err(Error::Unauthenticated),
+        }
+    }
+}
+
+/// 为 `name` 签发在 `ttl` 后过期的 token
+pub fn sign_token(name: &str, secret: &[u8], ttl: Duration) -> String {
+    let payload = format!("{}.{}", name, unix_time() + ttl.as_secs());
+    let signature = hex::encode(HMAC::mac(payload.as_bytes(), secret));
+    format!("{}.{}", payload, signature)
+}
+
+/// 每个请求都携带同一个 bearer token
+#[derive(Debug, Clone)]
+pub struct BearerToken(pub String);
+
+impl CredentialProvider for BearerToken {
+    fn headers(&self, _: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.insert(String::from(AUTHORIZATION), format!("Bearer {}", self.0));
+        headers
+    }
+}
+
+/// 客户端持有共享密钥，为每个请求签发短期有效的 token
+pub struct HmacCredentials {
+    name: String,
+    secret: Vec<u8>,
+    ttl: Duration,
+}
+
+impl HmacCredentials {
+    /// token 默认 60 秒后过期
+    pub fn new(name: &str, secret: &[u8]) -> Self {
+        Self {
+            name: String::from(name),
+            secret: secret.to_vec(),
+            ttl: Duration::from_secs(60),
+        }
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+}
+
+impl CredentialProvider for HmacCredentials {
+    fn headers(&self, function: &str) -> Headers {
+        BearerToken(sign_token(&self.name, &self.secret, self.ttl)).headers(function)
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// 比较 token 时不因第一个不同的字节提前返回
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use auth::{sign_token, Authenticator, BearerToken, CredentialProvider, HmacAuthenticator, HmacCredentials, StaticTokenAuthenticator};
+    use context::{Identity, RequestContext};
+    use error::Error;
+    use std::time::Duration;
+
+    #[test]
+    fn authenticator_test() {
+        let context = RequestContext::new("memory:auth".parse().unwrap());
+        let authenticator = StaticTokenAuthenticator::new().token("s3cr3t", "path_client");
+        assert_eq!(Ok(Identity::named("path_client")),
+                   authenticator.authenticate(&context, "add", &BearerToken(String::from("s3cr3t")).headers("add")));
+        assert_eq!(Err(Error::Unauthenticated),
+                   authenticator.authenticate(&context, "add", &BearerToken(String::from("guess")).headers("add")));
+        assert_eq!(Err(Error::Unauthenticated), authenticator.authenticate(&context, "add", &Default::default()));
+
+        let authenticator = HmacAuthenticator::new(b"key");
+        let credentials = HmacCredentials::new("route.planner", b"key");
+        assert_eq!(Ok(Identity::named("route.planner")), authenticator.authenticate(&context, "add", &credentials.headers("add")));
+        let forged = HmacCredentials::new("route.planner", b"other");
+        assert_eq!(Err(Error::Unauthenticated), authenticator.authenticate(&context, "add", &forged.headers("add")));
+        let expired = BearerToken(sign_token("route.planner", b"key", Duration::from_secs(0)));
+        assert_eq!(Err(Error::Unauthenticated), authenticator.authenticate(&context, "add", &expired.headers("add")));
+    }
+}
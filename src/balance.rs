@@ -0,0 +1,272 @@
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use address::Address;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 负载均衡器看到的节点状态
+#[derive(Debug, Clone, PartialEq)]
+pub struct EndpointView {
+    pub addr: Address,
+    /// 已发出但尚未收到响应的请求数
+    pub in_flight: usize,
+    /// 节点被摘除（ejected）或正在探活时为 false
+    pub available: bool,
+}
+
+/// 负载均衡策略：从 `endpoints` 中选出一个可用节点，返回其下标。
+/// `key` 为调用方通过 `MyRPCCall::key` 指定的请求键的哈希值。
+pub trait Balancer {
+    fn pick(&mut self, endpoints: &[EndpointView], key: Option<u64>) -> Option<usize>;
+}
+
+/// 连续失败 `max_failures` 次后摘除节点，`duration` 后发起探活，连接成功即重新加入。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ejection {
+    pub max_failures: u32,
+    pub duration: Duration,
+}
+
+impl Ejection {
+    pub fn new(max_failures: u32, duration: Duration) -> Self {
+        Self {
+            max_failures,
+            duration,
+        }
+    }
+}
+
+impl Default for Ejection {
+    fn default() -> Self {
+        Self::new(3, Duration::from_secs(10))
+    }
+}
+
+pub fn hash_key<K: Hash + ?Sized>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Default)]
+pub struct RoundRobin {
+    next: usize,
+}
+
+impl RoundRobin {
+    pub fn new() -> Self {
+        Self { next: 0 }
+    }
+}
+
+impl Balancer for RoundRobin {
+    fn pick(&mut self, endpoints: &[EndpointView], _key: Option<u64>) -> Option<usize> {
+        let len = endpoints.len();
+        for i in 0..len {
+            let index = (self.next + i) % len;
+            if endpoints[index].available {
+                self.next = index + 1;
+                return Some(index);
+            }
+        }
+        None
+    }
+}
+
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new() -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() ^ u64::from(d.subsec_nanos()))
+            .unwrap_or(0);
+        Self::with_seed(seed)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        // xorshift 的状态不能为 0
+        Self { state: seed | 1 }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Balancer for Random {
+    fn pick(&mut self, endpoints: &[EndpointView], _key: Option<u64>) -> Option<usize> {
+        let available: Vec<usize> = (0..endpoints.len()).filter(|&i| endpoints[i].available).collect();
+        if available.is_empty() {
+            return None;
+        }
+        let index = (self.next_u64() % available.len() as u64) as usize;
+        Some(available[index])
+    }
+}
+
+/// 选择在途请求最少的节点，相同时轮询，避免总是压在第一个节点上
+#[derive(Default)]
+pub struct LeastLoaded {
+    round_robin: RoundRobin,
+}
+
+impl LeastLoaded {
+    pub fn new() -> Self {
+        Self { round_robin: RoundRobin::new() }
+    }
+}
+
+impl Balancer for LeastLoaded {
+    fn pick(&mut self, endpoints: &[EndpointView], key: Option<u64>) -> Option<usize> {
+        let min = endpoints.iter().filter(|e| e.available).map(|e| e.in_flight).min()?;
+        let candidates: Vec<EndpointView> = endpoints.iter().map(|e| EndpointView {
+            available: e.available && e.in_flight == min,
+            ..e.clone()
+        }).collect();
+        self.round_robin.pick(&candidates, key)
+    }
+}
+
+/// 一致性哈希：相同的请求键总是落在同一节点上，节点增减时只影响相邻区间。
+/// 未指定请求键的调用退化为轮询。
+pub struct ConsistentHash {
+    replicas: usize,
+    ring: BTreeMap<u64, Address>,
+    addrs: Vec<Address>,
+    round_robin: RoundRobin,
+}
+
+impl ConsistentHash {
+    pub fn new() -> Self {
+        Self::with_replicas(100)
+    }
+
+    /// `replicas` 为每个节点在环上的虚拟节点数
+    pub fn with_replicas(replicas: usize) -> Self {
+        Self {
+            replicas,
+            ring: BTreeMap::new(),
+            addrs: Vec::new(),
+            round_robin: RoundRobin::new(),
+        }
+    }
+
+    fn rebuild(&mut self, endpoints: &[EndpointView]) {
+        self.addrs = endpoints.iter().map(|e| e.addr.clone()).collect();
+        self.ring.clear();
+        for addr in &self.addrs {
+            for replica in 0..self.replicas {
+                self.ring.insert(hash_key(&(addr, replica)), addr.clone());
+            }
+        }
+    }
+}
+
+impl Default for ConsistentHash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Balancer for ConsistentHash {
+    fn pick(&mut self, endpoints: &[EndpointView], key: Option<u64>) -> Option<usize> {
+        let key = match key {
+            Some(key) => key,
+            None => return self.round_robin.pick(endpoints, None),
+        };
+        if self.addrs.len() != endpoints.len() || self.addrs.iter().zip(endpoints).any(|(a, e)| *a != e.addr) {
+            self.rebuild(endpoints);
+        }
+        let ring = self.ring.range(key..).chain(self.ring.range(..key));
+        for (_, addr) in ring {
+            if let Some(index) = endpoints.iter().position(|e| e.addr == *addr && e.available) {
+                return Some(index);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use balance::*;
+
+    fn endpoints(n: usize) -> Vec<EndpointView> {
+        (0..n).map(|i| EndpointView {
+            addr: format!("127.0.0.1:{}", 7000 + i).parse().unwrap(),
+            in_flight: 0,
+            available: true,
+        }).collect()
+    }
+
+    #[test]
+    fn round_robin_test() {
+        let mut endpoints = endpoints(3);
+        let mut balancer = RoundRobin::new();
+        let picked: Vec<_> = (0..4).map(|_| balancer.pick(&endpoints, None).unwrap()).collect();
+        assert_eq!(vec![0, 1, 2, 0], picked);
+        endpoints[1].available = false;
+        let picked: Vec<_> = (0..3).map(|_| balancer.pick(&endpoints, None).unwrap()).collect();
+        assert_eq!(vec![2, 0, 2], picked);
+    }
+
+    #[test]
+    fn random_test() {
+        let mut endpoints = endpoints(3);
+        endpoints[0].available = false;
+        let mut balancer = Random::with_seed(42);
+        for _ in 0..100 {
+            assert_ne!(Some(0), balancer.pick(&endpoints, None));
+        }
+        for endpoint in endpoints.iter_mut() {
+            endpoint.available = false;
+        }
+        assert_eq!(None, balancer.pick(&endpoints, None));
+    }
+
+    #[test]
+    fn least_loaded_test() {
+        let mut endpoints = endpoints(3);
+        endpoints[0].in_flight = 2;
+        endpoints[1].in_flight = 1;
+        endpoints[2].in_flight = 1;
+        let mut balancer = LeastLoaded::new();
+        assert_eq!(Some(1), balancer.pick(&endpoints, None));
+        assert_eq!(Some(2), balancer.pick(&endpoints, None));
+        endpoints[1].available = false;
+        endpoints[2].available = false;
+        assert_eq!(Some(0), balancer.pick(&endpoints, None));
+    }
+
+    #[test]
+    fn consistent_hash_test() {
+        let mut endpoints = endpoints(4);
+        let mut balancer = ConsistentHash::new();
+        let keys: Vec<u64> = (0..100).map(|i| hash_key(&i)).collect();
+        let before: Vec<_> = keys.iter().map(|k| balancer.pick(&endpoints, Some(*k)).unwrap()).collect();
+        let again: Vec<_> = keys.iter().map(|k| balancer.pick(&endpoints, Some(*k)).unwrap()).collect();
+        assert_eq!(before, again);
+
+        // 摘除一个节点只影响原本落在它上面的键
+        endpoints[2].available = false;
+        for (key, index) in keys.iter().zip(before) {
+            let picked = balancer.pick(&endpoints, Some(*key)).unwrap();
+            if index != 2 {
+                assert_eq!(index, picked);
+            } else {
+                assert_ne!(2, picked);
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+#[cfg_attr(test, macro_use)]
+extern crate myrpc4rs;
+extern crate serde_json;
+extern crate tiny_http;
+
+use myrpc4rs::auth::AUTHORIZATION;
+use myrpc4rs::client::{MyRPCClient, MyRPCClientBuilder};
+use myrpc4rs::discovery::{FileResolver, Resolver};
+use myrpc4rs::error::Error;
+use myrpc4rs::reflection;
+use myrpc4rs::serialization::Format;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Method, Response, Server};
+
+/// HTTP/JSON 网关：`POST /rpc/<namespace>/<function>` 以 JSON 数组为参数调用后端函数，
+/// `GET /rpc/<namespace>` 列出后端注册的函数。namespace 是服务发现文件中的服务名。
+///
+/// 网关用 JSON 格式连接后端，参数和返回值原样转发，不需要知道它们的类型。
+/// HTTP 的 `Authorization` 头作为请求的 `authorization` 头转发，由后端校验。
+struct Gateway {
+    services: PathBuf,
+    resolver: FileResolver,
+    /// `MyRPCClient` 不能跨线程使用，每个工作线程各自连接后端
+    clients: HashMap<String, MyRPCClient>,
+}
+
+impl Gateway {
+    fn new<P: AsRef<Path>>(services: P) -> Self {
+        Self {
+            services: services.as_ref().to_path_buf(),
+            resolver: FileResolver::new(&services),
+            clients: HashMap::new(),
+        }
+    }
+
+    fn handle(&mut self, method: &Method, url: &str, authorization: Option<&str>, body: &[u8]) -> Reply {
+        let path = url.split('?').next().unwrap_or("");
+        let parts = match path.strip_prefix("/rpc/") {
+            Some(rest) => rest.split('/').collect::<Vec<&str>>(),
+            None => return error(404, "not found"),
+        };
+        let (namespace, function) = match (parts.as_slice(), method) {
+            ([namespace, function], &Method::Post) if !namespace.is_empty() && !function.is_empty() => (*namespace, *function),
+            ([namespace], &Method::Get) if !namespace.is_empty() => (*namespace, reflection::FUNCTIONS),
+            ([_, _], _) | ([_], _) => return error(405, "method not allowed"),
+            _ => return error(404, "not found"),
+        };
+        let params = if function == reflection::FUNCTIONS {
+            vec![]
+        } else {
+            match serde_json::from_slice::<Vec<Value>>(body) {
+                Ok(params) => params.iter().map(|param| serde_json::to_vec(param).unwrap()).collect(),
+                Err(_) => return error(400, "body must be a JSON array of arguments"),
+            }
+        };
+        if !self.clients.contains_key(namespace) {
+            if self.resolver.resolve(namespace).is_err() {
+                return error(404, &format!("namespace {} not found", namespace));
+            }
+            let client = MyRPCClientBuilder::with_resolver(namespace, FileResolver::new(&self.services))
+                .format(Format::Json)
+                .build();
+            self.clients.insert(String::from(namespace), client);
+        }
+        let client = self.clients.get_mut(namespace).unwrap();
+        let mut call = client.call(String::from(function), params);
+        if let Some(authorization) = authorization {
+            call = call.header(AUTHORIZATION, authorization);
+        }
+        match call.sync().result {
+            Ok(result) => Reply::new(200, result),
+            Err(err) => {
+                let mut reply = error(status(&err), &format!("{:?}", err));
+                // 按秒向上取整
+                if let Error::ResourceExhausted { retry_after_ms } = err {
+                    reply.retry_after = Some(retry_after_ms.div_ceil(1000)).filter(|seconds| *seconds > 0);
+                }
+                reply
+            }
+        }
+    }
+}
+
+/// HTTP 状态码和 JSON 响应体
+#[derive(Debug, PartialEq)]
+struct Reply {
+    status: u16,
+    body: Vec<u8>,
+    /// 限流时建议的重试等待秒数，作为 `Retry-After` 头返回
+    retry_after: Option<u64>,
+}
+
+impl Reply {
+    fn new(status: u16, body: Vec<u8>) -> Self {
+        Self { status, body, retry_after: None }
+    }
+}
+
+/// 调用失败时的 HTTP 状态码
+fn status(error: &Error) -> u16 {
+    match *error {
+        Error::FunctionNotFound => 404,
+        Error::ParamDeserializeFail => 400,
+        Error::Unauthenticated => 401,
+        Error::PermissionDenied => 403,
+        Error::ConnectionFail | Error::ResultDeserializeFail | Error::CorruptFrame => 502,
+        Error::NoAvailableEndpoint | Error::CircuitOpen => 503,
+        Error::DeadlineExceeded => 504,
+        Error::ResourceExhausted { .. } => 429,
+    }
+}
+
+fn error(status: u16, message: &str) -> Reply {
+    Reply::new(status, serde_json::to_vec(&serde_json::json!({ "error": message })).unwrap())
+}
+
+fn main() {
+    let addr = env::args().nth(1).unwrap_or_else(|| String::from("127.0.0.1:8080"));
+    let services = env::args().nth(2).unwrap_or_else(|| String::from("services.toml"));
+    let workers = env::args().nth(3).map_or(4, |workers| workers.parse().unwrap());
+    let server = Arc::new(Server::http(&addr).unwrap());
+    println!("myrpc-gateway listening on {}", addr);
+    let handles = (0..workers).map(|_| {
+        let server = Arc::clone(&server);
+        let services = services.clone();
+        thread::spawn(move || {
+            let mut gateway = Gateway::new(services);
+            for mut request in server.incoming_requests() {
+                let mut body = Vec::new();
+                let authorization = request.headers().iter()
+                    .find(|header| header.field.equiv("Authorization"))
+                    .map(|header| header.value.to_string());
+                let reply = match request.as_reader().read_to_end(&mut body) {
+                    Ok(_) => gateway.handle(request.method(), request.url(), authorization.as_deref(), &body),
+                    Err(_) => error(400, "failed to read body"),
+                };
+                let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+                let mut response = Response::from_data(reply.body).with_status_code(reply.status).with_header(content_type);
+                if let Some(seconds) = reply.retry_after {
+                    response.add_header(Header::from_bytes("Retry-After", seconds.to_string()).unwrap());
+                }
+                if let Err(e) = request.respond(response) {
+                    println!("ERR:响应失败: {}", e);
+                }
+            }
+        })
+    }).collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use myrpc4rs::serialization::Serializer;
+    use myrpc4rs::server::{MyRPCServer, ShutdownHandle};
+    use serde_json::{self, Value};
+    use std::env;
+    use std::fs;
+    use std::sync::mpsc;
+    use std::thread::{self, JoinHandle};
+    use tiny_http::Method;
+    use {Gateway, Reply};
+
+    /// 在新线程中运行的后端服务，drop 时停止
+    struct Backend {
+        shutdown: ShutdownHandle,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl Drop for Backend {
+        fn drop(&mut self) {
+            self.shutdown.shutdown();
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    fn spawn_backend<F>(setup: F) -> Backend where F: FnOnce() -> MyRPCServer + Send + 'static {
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let mut myrpc = setup();
+            sender.send(myrpc.shutdown_handle()).unwrap();
+            myrpc.start_server();
+        });
+        Backend { shutdown: receiver.recv().unwrap(), thread: Some(thread) }
+    }
+
+    #[test]
+    fn gateway_test() {
+        use myrpc4rs::limit::{Limits, RateLimit};
+
+        let server = spawn_backend(move || {
+            let myrpc = MyRPCServer::new("memory:gateway_test".parse().unwrap())
+                .limits(Limits::new().function("refresh", RateLimit::per_minute(1)));
+            myrpc_function!(myrpc,add,a<u32>,b<u32>,{
+                a + b
+            });
+            myrpc_function!(myrpc,refresh,road<u32>,{
+                road
+            });
+            myrpc
+        });
+        let services = env::temp_dir().join("myrpc4rs_gateway_test.toml");
+        fs::write(&services, "math = [\"memory:gateway_test\"]").unwrap();
+        let mut gateway = Gateway::new(&services);
+
+        assert_eq!(Reply::new(200, b"3".to_vec()), gateway.handle(&Method::Post, "/rpc/math/add", None, b"[1,2]"));
+        assert_eq!(400, gateway.handle(&Method::Post, "/rpc/math/add", None, b"[1,\"2\"]").status);
+        assert_eq!(400, gateway.handle(&Method::Post, "/rpc/math/add", None, b"{}").status);
+        assert_eq!(404, gateway.handle(&Method::Post, "/rpc/math/sub", None, b"[1,2]").status);
+        assert_eq!(404, gateway.handle(&Method::Post, "/rpc/graph/add", None, b"[1,2]").status);
+        assert_eq!(405, gateway.handle(&Method::Get, "/rpc/math/add", None, b"").status);
+        let reply = gateway.handle(&Method::Get, "/rpc/math", None, b"");
+        assert_eq!(200, reply.status);
+        let functions: Value = serde_json::from_slice(&reply.body).unwrap();
+        assert_eq!(serde_json::json!([{"name": "add", "idempotent": false}, {"name": "refresh", "idempotent": false}]), functions);
+        // 限流时带上建议的重试等待秒数
+        assert_eq!(200, gateway.handle(&Method::Post, "/rpc/math/refresh", None, b"[42]").status);
+        let reply = gateway.handle(&Method::Post, "/rpc/math/refresh", None, b"[42]");
+        assert_eq!((429, Some(60)), (reply.status, reply.retry_after));
+        drop(server);
+        assert_eq!(502, gateway.handle(&Method::Post, "/rpc/math/add", None, b"[1,2]").status);
+    }
+
+    #[test]
+    fn authorization_test() {
+        use myrpc4rs::auth::StaticTokenAuthenticator;
+
+        let _server = spawn_backend(move || {
+            let myrpc = MyRPCServer::new("memory:gateway_authorization_test".parse().unwrap())
+                .authenticator(StaticTokenAuthenticator::new().token("s3cr3t", "dashboard"));
+            myrpc_function!(myrpc,add,a<u32>,b<u32>,{
+                a + b
+            });
+            myrpc
+        });
+        let services = env::temp_dir().join("myrpc4rs_gateway_authorization_test.toml");
+        fs::write(&services, "math = [\"memory:gateway_authorization_test\"]").unwrap();
+        let mut gateway = Gateway::new(&services);
+
+        assert_eq!(401, gateway.handle(&Method::Post, "/rpc/math/add", None, b"[1,2]").status);
+        assert_eq!(401, gateway.handle(&Method::Post, "/rpc/math/add", Some("Bearer wrong"), b"[1,2]").status);
+        assert_eq!(Reply::new(200, b"3".to_vec()), gateway.handle(&Method::Post, "/rpc/math/add", Some("Bearer s3cr3t"), b"[1,2]"));
+    }
+}
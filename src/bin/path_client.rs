@@ -4,10 +4,13 @@ extern crate myrpc4rs;
 extern crate serde_derive;
 
 use myrpc4rs::serialization::Serializer;
-use myrpc4rs::client::MyRPCClient;
+use myrpc4rs::client::MyRPCClientBuilder;
+use myrpc4rs::discovery::FileResolver;
+use myrpc4rs::retry::RetryPolicy;
 use myrpc4rs::error::Error;
 use std::collections::HashSet;
 use std::cmp::Ordering;
+use std::env;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 struct Node {
@@ -26,20 +29,22 @@ impl Node {
         assert_eq!(next.from, self.index);
         self.path_set.insert(next);
     }
+    #[allow(dead_code)]
     fn get_shortest_patch(&self) -> &Path {
         let mut shortest_path = None;
         for path in &self.path_set {
-            if shortest_path == None {
+            if shortest_path.is_none() {
                 shortest_path = Some(path);
                 continue;
             }
 
-            if (shortest_path.unwrap() > path) {
+            if shortest_path.unwrap() > path {
                 shortest_path = Some(path);
             }
         };
         shortest_path.unwrap()
     }
+    #[allow(dead_code)]
     fn get_path_set(&self) -> &HashSet<Path> {
         &self.path_set
     }
@@ -97,7 +102,12 @@ fn main(){
     nodes[3].add_path(Path::new(3, 5, 5, 5));
     nodes[4].add_path(Path::new(4, 3, 15, 5));
     nodes[4].add_path(Path::new(4, 5, 15, 45));
-    let mut client = MyRPCClient::new("127.0.0.1:6181".parse().unwrap());
+    // 可以通过参数指定服务发现文件，例如 path_client services.toml
+    let builder = match env::args().nth(1) {
+        Some(path) => MyRPCClientBuilder::with_resolver("path_server", FileResolver::new(path)),
+        None => MyRPCClientBuilder::new(vec!["127.0.0.1:6181".parse().unwrap()]),
+    };
+    let mut client = builder.retry_policy(RetryPolicy::new(3)).build();
 
     let resp = myrpc_call_sync!(client,find_shortest_path,&nodes,&0usize,&5usize;<(Option<usize>, Vec<usize>)>);
     println!("{:?}", resp);
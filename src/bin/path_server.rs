@@ -5,9 +5,13 @@ extern crate serde_derive;
 
 use myrpc4rs::server::MyRPCServer;
 use myrpc4rs::serialization::Serializer;
+#[cfg(feature = "websocket")]
+use myrpc4rs::transport::DefaultTransport;
+#[cfg(feature = "websocket")]
+use myrpc4rs::ws::WsTransport;
 use std::collections::HashSet;
 use std::cmp::Ordering;
-use std::usize;
+use std::env;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 struct Node {
@@ -127,7 +131,7 @@ fn find_shortest_path(nodes: Vec<Node>, from: usize, to: usize)
     }
 }
 
-fn get_shortest_path(from: usize, to: usize, shortest_path: &Vec<Option<usize>>) -> Vec<usize> {
+fn get_shortest_path(from: usize, to: usize, shortest_path: &[Option<usize>]) -> Vec<usize> {
     let mut result = Vec::new();
     let mut current = match shortest_path[to] {
         Some(v) => v,
@@ -146,10 +150,20 @@ fn get_shortest_path(from: usize, to: usize, shortest_path: &Vec<Option<usize>>)
 }
 
 fn main() {
-    let mut myrpc = MyRPCServer::new("127.0.0.1:6181".parse().unwrap());
+    // 可以通过参数指定监听地址，以便启动多个实例
+    let addr = env::args().nth(1).unwrap_or_else(|| String::from("127.0.0.1:6181"));
+    let mut myrpc = MyRPCServer::new(addr.parse().unwrap());
+    // 第二个参数指定 WebSocket 地址，供网页用 JSON 直接调用
+    #[cfg(feature = "websocket")]
+    {
+        if let Some(ws) = env::args().nth(2) {
+            myrpc = myrpc.listen(ws.parse().unwrap(), WsTransport::new(DefaultTransport));
+        }
+    }
+    // 路径查询是只读的，声明为幂等以便客户端自动重试
     myrpc_function!(myrpc,find_shortest_path,nodes<Vec<Node>>,from<usize>,to<usize>,{
         find_shortest_path(nodes, from, to)
-    });
+    }).idempotent();
     myrpc.start_server();
 }
 
@@ -0,0 +1,229 @@
+use error::Error;
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use address::Address;
+use std::time::{Duration, Instant};
+
+/// 熔断器配置，客户端对每个节点上的每个函数分别统计
+///
+/// 最近 `window` 次调用中失败比例达到 `failure_ratio`（且调用次数不少于 `min_requests`）时熔断，
+/// 熔断期间调用直接返回 `Error::CircuitOpen`；`cooldown` 后进入半开状态，放行一次试探调用，
+/// 成功则恢复，失败则继续熔断。返回 `trip_on` 中的错误或耗时超过 `slow_call` 的调用都算失败。
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitBreaker {
+    window: usize,
+    min_requests: usize,
+    failure_ratio: f64,
+    slow_call: Duration,
+    cooldown: Duration,
+    trip_on: Vec<Error>,
+}
+
+impl CircuitBreaker {
+    pub fn new() -> Self {
+        Self {
+            window: 20,
+            min_requests: 10,
+            failure_ratio: 0.5,
+            slow_call: Duration::from_secs(5),
+            cooldown: Duration::from_secs(10),
+            trip_on: vec![Error::ConnectionFail],
+        }
+    }
+
+    pub fn window(mut self, window: usize) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn failure_ratio(mut self, failure_ratio: f64, min_requests: usize) -> Self {
+        self.failure_ratio = failure_ratio;
+        self.min_requests = min_requests;
+        self
+    }
+
+    pub fn slow_call(mut self, slow_call: Duration) -> Self {
+        self.slow_call = slow_call;
+        self
+    }
+
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// 计为失败的错误，只比较枚举成员，不比较携带的数据
+    pub fn trip_on(mut self, trip_on: Vec<Error>) -> Self {
+        self.trip_on = trip_on;
+        self
+    }
+
+    fn is_failure(&self, error: Option<&Error>, latency: Duration) -> bool {
+        if latency > self.slow_call {
+            return true;
+        }
+        match error {
+            Some(error) => self.trip_on.iter().any(|e| mem::discriminant(e) == mem::discriminant(error)),
+            None => false,
+        }
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    Closed,
+    Open(Instant),
+    /// `trial` 为 true 表示试探调用已经发出
+    HalfOpen { trial: bool },
+}
+
+struct Circuit {
+    state: State,
+    outcomes: VecDeque<bool>,
+}
+
+impl Circuit {
+    fn new() -> Self {
+        Self {
+            state: State::Closed,
+            outcomes: VecDeque::new(),
+        }
+    }
+
+    fn allow(&mut self, now: Instant) -> bool {
+        match self.state {
+            State::Closed => true,
+            State::Open(until) if now >= until => {
+                self.state = State::HalfOpen { trial: false };
+                true
+            }
+            State::Open(_) => false,
+            State::HalfOpen { trial } => !trial,
+        }
+    }
+
+    fn dispatched(&mut self) {
+        if let State::HalfOpen { .. } = self.state {
+            self.state = State::HalfOpen { trial: true };
+        }
+    }
+
+    fn cancelled(&mut self) {
+        if let State::HalfOpen { .. } = self.state {
+            self.state = State::HalfOpen { trial: false };
+        }
+    }
+
+    fn record(&mut self, config: &CircuitBreaker, failure: bool, now: Instant) {
+        match self.state {
+            State::HalfOpen { .. } => {
+                self.outcomes.clear();
+                self.state = if failure { State::Open(now + config.cooldown) } else { State::Closed };
+            }
+            State::Closed => {
+                self.outcomes.push_back(failure);
+                while self.outcomes.len() > config.window {
+                    self.outcomes.pop_front();
+                }
+                let failures = self.outcomes.iter().filter(|f| **f).count();
+                if self.outcomes.len() >= config.min_requests
+                    && failures as f64 >= config.failure_ratio * self.outcomes.len() as f64 {
+                    self.outcomes.clear();
+                    self.state = State::Open(now + config.cooldown);
+                }
+            }
+            // 熔断前发出的请求晚到的结果
+            State::Open(_) => {}
+        }
+    }
+}
+
+/// 客户端 NET 层线程中按节点和函数名维护的熔断状态
+pub(crate) struct Circuits {
+    config: CircuitBreaker,
+    circuits: HashMap<(Address, String), Circuit>,
+}
+
+impl Circuits {
+    pub fn new(config: CircuitBreaker) -> Self {
+        Self {
+            config,
+            circuits: HashMap::new(),
+        }
+    }
+
+    fn circuit(&mut self, addr: &Address, name: &str) -> &mut Circuit {
+        self.circuits.entry((addr.clone(), String::from(name))).or_insert_with(Circuit::new)
+    }
+
+    pub fn allow(&mut self, addr: &Address, name: &str) -> bool {
+        self.circuit(addr, name).allow(Instant::now())
+    }
+
+    pub fn dispatched(&mut self, addr: &Address, name: &str) {
+        self.circuit(addr, name).dispatched();
+    }
+
+    /// 试探调用被取消（例如对冲请求的另一路先返回），允许再次试探
+    pub fn cancelled(&mut self, addr: &Address, name: &str) {
+        self.circuit(addr, name).cancelled();
+    }
+
+    pub fn record(&mut self, addr: &Address, name: &str, error: Option<&Error>, latency: Duration) {
+        let failure = self.config.is_failure(error, latency);
+        let config = self.config.clone();
+        self.circuit(addr, name).record(&config, failure, Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use breaker::{CircuitBreaker, Circuit, State};
+    use error::Error;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn circuit_test() {
+        let config = CircuitBreaker::new().window(4).failure_ratio(0.5, 4).cooldown(Duration::from_secs(10));
+        let now = Instant::now();
+        let mut circuit = Circuit::new();
+        for failure in &[true, false, false] {
+            assert!(circuit.allow(now));
+            circuit.record(&config, *failure, now);
+        }
+        assert_eq!(State::Closed, circuit.state);
+        circuit.record(&config, true, now);
+        assert_eq!(State::Open(now + Duration::from_secs(10)), circuit.state);
+        assert!(!circuit.allow(now));
+
+        // 冷却后半开，只放行一次试探
+        let later = now + Duration::from_secs(10);
+        assert!(circuit.allow(later));
+        circuit.dispatched();
+        assert!(!circuit.allow(later));
+        circuit.record(&config, true, later);
+        assert_eq!(State::Open(later + Duration::from_secs(10)), circuit.state);
+
+        let later = later + Duration::from_secs(10);
+        assert!(circuit.allow(later));
+        circuit.dispatched();
+        circuit.record(&config, false, later);
+        assert_eq!(State::Closed, circuit.state);
+        assert!(circuit.allow(later));
+    }
+
+    #[test]
+    fn is_failure_test() {
+        let config = CircuitBreaker::new().slow_call(Duration::from_millis(100));
+        assert!(config.is_failure(Some(&Error::ConnectionFail), Duration::from_millis(1)));
+        assert!(!config.is_failure(Some(&Error::FunctionNotFound), Duration::from_millis(1)));
+        assert!(!config.is_failure(None, Duration::from_millis(1)));
+        assert!(config.is_failure(None, Duration::from_millis(200)));
+    }
+}
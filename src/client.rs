@@ -1,98 +1,555 @@
-use std::net::SocketAddr;
-use serialization::BincodeSerializer;
-use common::Request;
-use common::Response;
+use address::Address;
+use serialization::{Format, Serializer};
+pub use common::{Request, Response};
 use std::rc::Rc;
-use std::u32;
 use std::sync::mpsc;
-use std::sync::mpsc::Sender;
-use net::Client;
+use futures::sync::mpsc as futures_mpsc;
+use futures::{future, Future};
+use net::{Callback, Client, Command, DEFAULT_MAX_FRAME_SIZE, DEFAULT_WRITE_BUFFER_LIMIT};
+use std::cell::RefCell;
 use std::thread;
-use std::sync::Arc;
 use std::thread::JoinHandle;
-use std::mem::swap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+use std::sync::mpsc::RecvTimeoutError;
+use context::{self, RequestContext, TIMEOUT};
+use balance::{Balancer, RoundRobin, Ejection, hash_key};
+use discovery::{Resolver, StaticResolver};
+use retry::RetryPolicy;
+use breaker::CircuitBreaker;
+use hedge::HedgingPolicy;
+use compression::Compression;
+use auth::{CredentialProvider, Headers};
+#[cfg(feature = "tls")]
+use tls::{ClientTlsConfig, TlsTransport};
+use std::mem;
+use transport::{DefaultTransport, Transport};
+use error::Error;
+use interceptor::ClientInterceptor;
+use keepalive::Heartbeat;
+use stream::{CallStream, RequestSink, Window, DEFAULT_WINDOW};
+use common::{StreamFrame, HANDSHAKE_ID};
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+use pubsub;
 
 pub struct MyRPCClient {
     request_id: u32,
-    sender: Rc<Sender<(Request, Box<FnMut(&Rc<BincodeSerializer>, &Response) + Send>)>>,
+    sender: Rc<Queue>,
     client_thread_handle: Option<JoinHandle<()>>,
-    serializer: BincodeSerializer,
+    serializer: Format,
+    interceptors: Interceptors,
 }
 
-impl MyRPCClient {
-    pub fn new(socket_addr: SocketAddr) -> Self {
-        let (sender, receiver) = mpsc::channel();
-        let client_thread_handle = thread::spawn(move || {
-            let client = Client::new(socket_addr, Rc::new(BincodeSerializer::new()));
-            client.start(&Arc::new(receiver));
-        });
-        Self {
-            request_id: 0,
-            sender: Rc::new(sender),
-            client_thread_handle: Some(client_thread_handle),
-            serializer: BincodeSerializer::new(),
+type Interceptors = Arc<Vec<Box<dyn ClientInterceptor + Send + Sync>>>;
+
+/// 调用队列已满时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backpressure {
+    /// 阻塞调用方线程，直到 NET 层线程取走队列中的调用
+    Wait,
+    /// 立即以 `Error::ResourceExhausted` 失败
+    Reject,
+}
+
+/// 客户端线程到 NET 层线程的有界队列
+pub(crate) struct Queue {
+    sender: RefCell<futures_mpsc::Sender<Command>>,
+    backpressure: Backpressure,
+}
+
+impl Queue {
+    /// 队列已满或 NET 层线程已经退出时，直接以错误调用回调
+    fn call(&self, format: &Format, request: Request, key: Option<u64>, callback: Callback) {
+        self.submit(format, Command::Call(request, key, callback));
+    }
+
+    /// 双向流式调用，失败时关闭发送消息的额度
+    fn open(&self, format: &Format, request: Request, key: Option<u64>, callback: Callback, window: Arc<Window>) {
+        self.submit(format, Command::Open(request, key, callback, window));
+    }
+
+    fn submit(&self, format: &Format, command: Command) {
+        let failed = {
+            let mut sender = self.sender.borrow_mut();
+            if self.backpressure == Backpressure::Wait {
+                let _ = future::poll_fn(|| sender.poll_ready()).wait();
+            }
+            sender.try_send(command).err()
+        };
+        if let Some(e) = failed {
+            let error = if e.is_full() { Error::ResourceExhausted { retry_after_ms: 0 } } else { Error::ConnectionFail };
+            match e.into_inner() {
+                Command::Call(request, _, mut callback) => callback(format, &Response::err(request, error)),
+                Command::Open(request, _, mut callback, window) => {
+                    window.close();
+                    callback(format, &Response::err(request, error));
+                }
+                _ => {}
+            }
         }
     }
+
+    /// 双向流式调用中的消息，已经有了发送额度，总是等待队列中的空间
+    pub fn message(&self, request: Request) -> Result<(), Error> {
+        let mut sender = self.sender.borrow_mut();
+        let _ = future::poll_fn(|| sender.poll_ready()).wait();
+        sender.try_send(Command::Message(request)).map_err(|_| Error::ConnectionFail)
+    }
+
+    pub fn window(&self, id: u32, credit: u32) {
+        self.control(Command::Window(id, credit));
+    }
+
+    pub fn cancel(&self, id: u32) {
+        self.control(Command::Cancel(id));
+    }
+
+    /// 克隆的发送端总能再发出一条消息，额度和取消不会因为队列已满而丢失，也不会阻塞
+    fn control(&self, command: Command) {
+        let _ = self.sender.borrow().clone().try_send(command);
+    }
+
+    /// 停止命令总是等待队列中的调用被取走
+    fn stop(&self) {
+        let mut sender = self.sender.borrow_mut();
+        let _ = future::poll_fn(|| sender.poll_ready()).wait();
+        let _ = sender.try_send(Command::Stop);
+    }
+}
+
+impl MyRPCClient {
+    pub fn new(address: Address) -> Self {
+        MyRPCClientBuilder::new(vec![address]).build()
+    }
+
+    /// 在服务端处理函数中调用时，继承当前请求的元数据和截止时间
     pub fn call(&mut self, name: String, params: Vec<Vec<u8>>) -> MyRPCCall {
+        let current = context::current();
         let request = Request {
             id: self.request_id,
             name,
             params,
+            headers: current.as_ref().map_or_else(Headers::new, RequestContext::propagated_headers),
+            one_way: false,
+            stream: None,
         };
-        self.request_id = (self.request_id + 1) % (u32::MAX - 1);
-        MyRPCCall { sender: self.sender.clone(), request }
+        // 最大的三个 id 留给握手、反射和无法识别的请求的错误响应
+        self.request_id = (self.request_id + 1) % HANDSHAKE_ID;
+        let deadline = current.and_then(|context| context.deadline());
+        MyRPCCall {
+            sender: self.sender.clone(),
+            request,
+            key: None,
+            deadline,
+            format: self.serializer,
+            interceptors: Arc::clone(&self.interceptors),
+        }
+    }
+
+    /// 订阅服务端以 `Publisher::publish` 发布到主题的值，只收到订阅之后发布的值。
+    /// 订阅在 drop 时取消；连接断开或者读取太慢时以错误结束，需要重新订阅。
+    /// 多个节点时订阅只发往其中一个节点
+    pub fn subscribe<R: DeserializeOwned>(&mut self, topic: &str) -> CallStream<R> {
+        let topic = Serializer::serialize(&self.serializer, &topic).unwrap();
+        self.call(String::from(pubsub::SUBSCRIBE), vec![topic]).stream()
     }
 
-    pub fn get_serializer(&self) -> &BincodeSerializer {
+    pub fn get_serializer(&self) -> &Format {
         &self.serializer
     }
 }
 
 impl Drop for MyRPCClient {
     fn drop(&mut self) {
-        let req = Request {
-            id: u32::MAX,
-            name: String::from("stop"),
-            params: vec![],
+        self.sender.stop();
+        if let Some(client_thread_handle) = self.client_thread_handle.take() {
+            client_thread_handle.join().unwrap();
+        }
+    }
+}
+
+/// 构造连接多个服务端节点的客户端
+///
+/// ```no_run
+/// use myrpc4rs::client::MyRPCClientBuilder;
+/// use myrpc4rs::balance::LeastLoaded;
+///
+/// let client = MyRPCClientBuilder::new(vec!["127.0.0.1:6181".parse().unwrap(),
+///                                           "127.0.0.1:6182".parse().unwrap()])
+///     .balancer(LeastLoaded::new())
+///     .build();
+/// ```
+pub struct MyRPCClientBuilder {
+    pub(crate) service: String,
+    pub(crate) resolver: Box<dyn Resolver + Send>,
+    pub(crate) refresh_interval: Duration,
+    pub(crate) balancer: Box<dyn Balancer + Send>,
+    pub(crate) ejection: Ejection,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) circuit_breaker: Option<CircuitBreaker>,
+    pub(crate) hedging_policy: Option<HedgingPolicy>,
+    pub(crate) transport: Box<dyn Transport + Send>,
+    pub(crate) format: Format,
+    pub(crate) compression: Option<Compression>,
+    pub(crate) checksum: bool,
+    pub(crate) credentials: Option<Box<dyn CredentialProvider + Send>>,
+    pub(crate) heartbeat: Option<Heartbeat>,
+    pub(crate) write_buffer_limit: usize,
+    pub(crate) max_frame_size: usize,
+    queue_capacity: usize,
+    backpressure: Backpressure,
+    interceptors: Vec<Box<dyn ClientInterceptor + Send + Sync>>,
+}
+
+impl MyRPCClientBuilder {
+    pub fn new(endpoints: Vec<Address>) -> Self {
+        Self::with_resolver("", StaticResolver::new(endpoints))
+    }
+
+    /// 通过服务发现获取 `service` 的节点列表，节点列表变化时自动重新均衡
+    ///
+    /// ```no_run
+    /// use myrpc4rs::client::MyRPCClientBuilder;
+    /// use myrpc4rs::discovery::FileResolver;
+    ///
+    /// let client = MyRPCClientBuilder::with_resolver("path_server", FileResolver::new("services.toml"))
+    ///     .build();
+    /// ```
+    pub fn with_resolver<R>(service: &str, resolver: R) -> Self
+        where R: 'static + Resolver + Send {
+        Self {
+            service: String::from(service),
+            resolver: Box::new(resolver),
+            refresh_interval: Duration::from_secs(5),
+            balancer: Box::new(RoundRobin::new()),
+            ejection: Ejection::default(),
+            retry_policy: RetryPolicy::default(),
+            circuit_breaker: None,
+            hedging_policy: None,
+            transport: Box::new(DefaultTransport),
+            format: Format::Bincode,
+            compression: None,
+            checksum: false,
+            credentials: None,
+            heartbeat: None,
+            write_buffer_limit: DEFAULT_WRITE_BUFFER_LIMIT,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            queue_capacity: 1024,
+            backpressure: Backpressure::Wait,
+            interceptors: Vec::new(),
+        }
+    }
+
+    pub fn balancer<B>(mut self, balancer: B) -> Self
+        where B: 'static + Balancer + Send {
+        self.balancer = Box::new(balancer);
+        self
+    }
+
+    /// 连续失败 `max_failures` 次后摘除节点，`duration` 后探活
+    pub fn ejection(mut self, max_failures: u32, duration: Duration) -> Self {
+        self.ejection = Ejection::new(max_failures, duration);
+        self
+    }
+
+    /// 失败重试策略，默认不重试
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// 按节点和函数名熔断，默认不启用
+    pub fn circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    /// 幂等函数的对冲请求策略，默认不启用
+    pub fn hedging_policy(mut self, hedging_policy: HedgingPolicy) -> Self {
+        self.hedging_policy = Some(hedging_policy);
+        self
+    }
+
+    /// 使用 TLS 连接服务端，在当前传输层之上加密
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: ClientTlsConfig) -> Self {
+        let inner = mem::replace(&mut self.transport, Box::new(DefaultTransport));
+        self.transport = Box::new(TlsTransport::new(inner).client(tls));
+        self
+    }
+
+    /// 自定义传输层，默认按地址类型使用 TCP、Unix 域套接字或进程内传输
+    pub fn transport<T: 'static + Transport + Send>(mut self, transport: T) -> Self {
+        self.transport = Box::new(transport);
+        self
+    }
+
+    /// 参数和返回值的序列化格式，默认为 bincode。连接建立时通过握手告知服务端，
+    /// 例如 HTTP 网关用 JSON 转发调用而不需要知道参数类型
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// 超过阈值的帧用 LZ4 压缩，连接建立时与服务端协商，服务端没有启用时不压缩
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// 每一帧附带 CRC32C 校验和，服务端按握手时的要求同样附带。
+    /// 校验失败时关闭连接，在途请求以 `Error::CorruptFrame` 失败
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// 每个请求附带的凭据，例如 `BearerToken` 或 `HmacCredentials`
+    pub fn credentials<C>(mut self, credentials: C) -> Self
+        where C: 'static + CredentialProvider + Send {
+        self.credentials = Some(Box::new(credentials));
+        self
+    }
+
+    /// 在每个连接上发送心跳，服务端超时没有回应时关闭连接，在途请求以 `Error::ConnectionFail` 失败
+    pub fn heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+
+    /// 所有连接的发送缓冲区合计的上限，默认 1 MiB。达到上限后新的调用留在队列中
+    pub fn write_buffer_limit(mut self, write_buffer_limit: usize) -> Self {
+        self.write_buffer_limit = write_buffer_limit;
+        self
+    }
+
+    /// 收到的帧的长度上限，默认 16 MiB。服务端声明更长的帧时关闭连接，在途请求以 `Error::ConnectionFail` 失败
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// 等待 NET 层线程取出的调用数上限，默认 1024，队列满时按 `backpressure` 处理
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> Self {
+        self.queue_capacity = queue_capacity;
+        self
+    }
+
+    /// 队列满时等待还是拒绝新的调用，默认等待
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// 添加拦截器，`before` 按添加顺序调用，`after` 按相反顺序调用
+    pub fn interceptor<I>(mut self, interceptor: I) -> Self
+        where I: 'static + ClientInterceptor + Send + Sync {
+        self.interceptors.push(Box::new(interceptor));
+        self
+    }
+
+    /// 重新解析节点列表的间隔
+    pub fn refresh_interval(mut self, refresh_interval: Duration) -> Self {
+        self.refresh_interval = refresh_interval;
+        self
+    }
+
+    pub fn build(mut self) -> MyRPCClient {
+        let (sender, receiver) = futures_mpsc::channel(self.queue_capacity);
+        let sender = Queue {
+            sender: RefCell::new(sender),
+            backpressure: self.backpressure,
         };
-        let callback = |_: &Rc<BincodeSerializer>, _: &Response| {};
-        self.sender.send((req, Box::new(callback))).unwrap();
-        let mut client_thread_handle = None;
-        swap(&mut self.client_thread_handle, &mut client_thread_handle);
-        client_thread_handle.unwrap().join().unwrap();
+        let serializer = self.format;
+        let interceptors = Arc::new(mem::take(&mut self.interceptors));
+        let client_thread_handle = thread::spawn(move || {
+            let client = Client::new(self);
+            client.start(receiver);
+        });
+        MyRPCClient {
+            request_id: 0,
+            sender: Rc::new(sender),
+            client_thread_handle: Some(client_thread_handle),
+            serializer,
+            interceptors,
+        }
     }
 }
 
 pub struct MyRPCCall {
-    sender: Rc<Sender<(Request, Box<FnMut(&Rc<BincodeSerializer>, &Response) + Send>)>>,
+    sender: Rc<Queue>,
     request: Request,
+    key: Option<u64>,
+    deadline: Option<Instant>,
+    format: Format,
+    interceptors: Interceptors,
 }
 
 impl MyRPCCall {
+    /// 指定请求键，配合 `ConsistentHash` 使相同键的请求落在同一节点
+    pub fn key<K: Hash + ?Sized>(mut self, key: &K) -> Self {
+        self.key = Some(hash_key(key));
+        self
+    }
+
+    /// 附加请求头，服务端通过 `RequestContext::metadata` 读取
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.request.headers.insert(String::from(name), String::from(value));
+        self
+    }
+
+    /// 超时时间，服务端据此得到截止时间；继承的截止时间更早时以其为准
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        let deadline = Instant::now() + timeout;
+        self.deadline = Some(self.deadline.map_or(deadline, |current| current.min(deadline)));
+        self
+    }
+
+    /// 超过截止时间仍未收到响应时返回 `Error::DeadlineExceeded`
     pub fn sync(&self) -> Response {
+        let started = Instant::now();
+        let mut request = self.request();
+        if let Some((response, ran)) = self.before(&mut request) {
+            return after(&self.interceptors[..ran], &request, response, started);
+        }
         let (sender, receiver) = mpsc::channel();
-        let callback = move |_: &Rc<BincodeSerializer>, resp: &Response| {
-            sender.send(resp.clone()).unwrap();
+        let callback = move |_: &Format, resp: &Response| {
+            // 调用方可能已经超时返回
+            let _ = sender.send(resp.clone());
+        };
+        self.send(request.clone(), Box::new(callback));
+        let result = match self.deadline {
+            Some(deadline) => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())).map_err(|e| match e {
+                RecvTimeoutError::Timeout => Error::DeadlineExceeded,
+                RecvTimeoutError::Disconnected => Error::ConnectionFail,
+            }),
+            None => receiver.recv().map_err(|_| Error::ConnectionFail),
+        };
+        // NET 层线程意外退出时回调被丢弃，不应让调用方 panic
+        let response = result.unwrap_or_else(|error| Response::err(self.request.clone(), error));
+        after(&self.interceptors, &request, response, started)
+    }
+
+    pub fn async<F>(&mut self, mut callback: F)
+        where F: 'static + FnMut(&Format, &Response) + Send {
+        let started = Instant::now();
+        let mut request = self.request();
+        if let Some((response, ran)) = self.before(&mut request) {
+            return callback(&self.format, &after(&self.interceptors[..ran], &request, response, started));
+        }
+        if self.interceptors.is_empty() {
+            return self.send(request, Box::new(callback));
+        }
+        let interceptors = Arc::clone(&self.interceptors);
+        let sent = request.clone();
+        let callback = move |format: &Format, response: &Response| {
+            callback(format, &after(&interceptors, &sent, response.clone(), started));
         };
-        self.sender.send((self.request.clone(), Box::new(callback))).unwrap();
-        return receiver.recv().unwrap();
+        self.send(request, Box::new(callback));
     }
 
-    pub fn async<F: 'static>(&mut self, callback: F)
-        where F: FnMut(&Rc<BincodeSerializer>, &Response) + Send {
-        self.sender.send((self.request.clone(), Box::new(callback))).unwrap();
+    /// 调用流式函数，返回的流逐个产出服务端的值。收到第一个值之后不再重试或对冲，
+    /// `after` 拦截器在流结束时调用一次
+    pub fn stream<R: DeserializeOwned>(&self) -> CallStream<R> {
+        let mut request = self.request();
+        // 客户端不发送消息
+        request.stream = Some(StreamFrame { sequence: 0, end: true });
+        self.open(request, None)
+    }
+
+    /// 双向流式调用：通过返回的 `RequestSink` 逐个发送消息，同时从 `CallStream` 逐个读取服务端的值。
+    /// 服务端只返回一个值时即为客户端流式调用。调用不会重试或对冲
+    pub fn bidi<R: DeserializeOwned>(&self) -> (RequestSink, CallStream<R>) {
+        let mut request = self.request();
+        request.stream = Some(StreamFrame { sequence: 0, end: false });
+        let window = Arc::new(Window::new(DEFAULT_WINDOW));
+        let sink = RequestSink::new(&request, self.format, Rc::clone(&self.sender), Arc::clone(&window));
+        (sink, self.open(request, Some(window)))
+    }
+
+    fn open<R: DeserializeOwned>(&self, mut request: Request, window: Option<Arc<Window>>) -> CallStream<R> {
+        let started = Instant::now();
+        let (sender, receiver) = futures_mpsc::unbounded();
+        if let Some((response, ran)) = self.before(&mut request) {
+            let _ = sender.unbounded_send(after(&self.interceptors[..ran], &request, response, started));
+            if let Some(window) = window {
+                window.close();
+            }
+            return CallStream::new(receiver, self.format, None, request.id);
+        }
+        let interceptors = Arc::clone(&self.interceptors);
+        let sent = request.clone();
+        let callback = move |_: &Format, response: &Response| {
+            let response = if response.is_end() {
+                after(&interceptors, &sent, response.clone(), started)
+            } else {
+                response.clone()
+            };
+            // 调用方可能已经丢弃了流
+            let _ = sender.unbounded_send(response);
+        };
+        let id = request.id;
+        match window {
+            Some(window) => self.sender.open(&self.format, request, self.key, Box::new(callback), window),
+            None => self.send(request, Box::new(callback)),
+        }
+        CallStream::new(receiver, self.format, Some(Rc::clone(&self.sender)), id)
+    }
+
+    /// 单向调用：请求写入连接后即返回，服务端不返回响应，`after` 拦截器不会被调用。
+    /// 只报告没能交给 NET 层的失败，例如队列已满或 NET 层线程已经退出；服务端的执行失败只记录在服务端日志中
+    pub fn one_way(&self) -> Result<(), Error> {
+        let mut request = self.request();
+        request.one_way = true;
+        if let Some((response, _)) = self.before(&mut request) {
+            return response.result.map(|_| ());
+        }
+        let (sender, receiver) = mpsc::channel();
+        let callback = move |_: &Format, resp: &Response| {
+            let _ = sender.send(resp.result.clone().map(|_| ()));
+        };
+        self.send(request, Box::new(callback));
+        receiver.try_recv().unwrap_or(Ok(()))
+    }
+
+    fn send(&self, request: Request, callback: Callback) {
+        self.sender.call(&self.format, request, self.key, callback);
+    }
+
+    /// 依次调用拦截器的 `before`，某个拦截器直接给出响应时返回该响应和在它之前的拦截器数量
+    fn before(&self, request: &mut Request) -> Option<(Response, usize)> {
+        self.interceptors.iter().enumerate()
+            .find_map(|(i, interceptor)| interceptor.before(request).map(|response| (response, i)))
+    }
+
+    /// 发送时把截止时间换算为剩余时间
+    fn request(&self) -> Request {
+        let mut request = self.request.clone();
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            request.headers.insert(String::from(TIMEOUT), remaining.as_millis().to_string());
+        }
+        request
     }
 }
 
+/// 按相反顺序调用拦截器的 `after`
+fn after(interceptors: &[Box<dyn ClientInterceptor + Send + Sync>], request: &Request, mut response: Response, started: Instant) -> Response {
+    for interceptor in interceptors.iter().rev() {
+        interceptor.after(request, &mut response, started.elapsed());
+    }
+    response
+}
+
 #[macro_export]
 macro_rules! myrpc_call_async {
     ($myrpc_client:expr, $function_name:expr, $($param:expr),+ ; $response:ident<$t:ty> $myrpc_block:block) => {
 
-        let mut params = Vec::new();
-        $(params.push($myrpc_client.get_serializer().serialize($param).unwrap());)+
+        let params = vec![$($myrpc_client.get_serializer().serialize($param).unwrap()),+];
         $myrpc_client.call(String::from(stringify!($function_name)), params).async(|serializer,response|{
             let $response:Result<$t,&Error> = match &response.result {
-                Ok(bytes)=>Ok(serializer.deserialize(&bytes).unwrap()),
+                Ok(bytes)=>serializer.deserialize(&bytes).map_err(|_| &Error::ResultDeserializeFail),
                 Err(error)=>Err(error),
             };
             $myrpc_block;
@@ -104,11 +561,10 @@ macro_rules! myrpc_call_async {
 macro_rules! myrpc_call_sync {
     ($myrpc_client:expr, $function_name:expr, $($param:expr),+ ;<$t:ty>) => {
         {
-            let mut params = Vec::new();
-            $(params.push($myrpc_client.get_serializer().serialize($param).unwrap());)+
+            let params = vec![$($myrpc_client.get_serializer().serialize($param).unwrap()),+];
             let response = $myrpc_client.call(String::from(stringify!($function_name)), params).sync();
             let result:Result<$t,Error> = match response.result {
-                Ok(bytes)=>Ok($myrpc_client.get_serializer().deserialize(&bytes).unwrap()),
+                Ok(bytes)=>$myrpc_client.get_serializer().deserialize(&bytes).map_err(|_| Error::ResultDeserializeFail),
                 Err(error)=>Err(error),
             };
             result
@@ -118,17 +574,934 @@ macro_rules! myrpc_call_sync {
 #[cfg(test)]
 mod tests {
     use client::MyRPCClient;
+    use client::MyRPCClientBuilder;
     use serialization::Serializer;
     use error::Error;
+    use server::MyRPCServer;
+    use testing::{spawn_test_server, TestServer};
+    use balance::RoundRobin;
+    use retry::RetryPolicy;
+    use breaker::CircuitBreaker;
+    use hedge::HedgingPolicy;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use discovery::FileResolver;
+    use std::env;
+    use std::fs;
+    use std::thread;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+    use address::Address;
+    use transport::{Connecting, Incoming, MemoryTransport, Socket, Transport};
+    use futures::{Future, Poll, Stream};
+    use tokio_io::{AsyncRead, AsyncWrite};
+    use tokio_core::reactor::Handle;
+    use std::io;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use common::HANDSHAKE_ID;
 
     #[test]
     fn client_test() {
-        let mut client = MyRPCClient::new("127.0.0.1:8080".parse().unwrap());
-        myrpc_call_async!(client,test1,&8,&4;aa <u32>{
-            println!("{:?}",aa);
+        let _server = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new("memory:client_test".parse().unwrap());
+            myrpc_function!(myrpc,test1,param1<u32>,param2<u32>,{
+                param1+param2
+            });
+            myrpc
+        });
+        {
+            let mut client = MyRPCClient::new("memory:client_test".parse().unwrap());
+            let (result_sender, result_receiver) = mpsc::channel();
+            let params = vec![client.get_serializer().serialize(&8u32).unwrap(), client.get_serializer().serialize(&4u32).unwrap()];
+            client.call(String::from("test1"), params).async(move |serializer, response| {
+                let aa: Result<u32, Error> = match response.result {
+                    Ok(ref bytes) => serializer.deserialize(bytes).map_err(|_| Error::ResultDeserializeFail),
+                    Err(ref error) => Err(error.clone()),
+                };
+                result_sender.send(aa).unwrap();
+            });
+
+            let resp = myrpc_call_sync!(client,test1,&16u32,&24u32;<u32>);
+            assert_eq!(Ok(40), resp);
+            assert_eq!(Ok(12), result_receiver.recv().unwrap());
+
+            // 用户请求的 id 在内部请求的 id 之前回绕
+            client.request_id = HANDSHAKE_ID - 1;
+            assert_eq!(Ok(3), myrpc_call_sync!(client,test1,&1u32,&2u32;<u32>));
+            assert_eq!(0, client.request_id);
+            assert_eq!(Ok(3), myrpc_call_sync!(client,test1,&1u32,&2u32;<u32>));
+        }
+    }
+
+    /// 在 `memory:<name>` 上运行的服务端，`name` 函数返回自己的名字
+    fn spawn_named_server(name: &'static str) -> TestServer {
+        spawn_test_server(move || {
+            let myrpc = MyRPCServer::new(format!("memory:{}", name).parse().unwrap());
+            myrpc.register_function(String::from("name"), move |serializer, _| {
+                Ok(serializer.serialize(&name).unwrap())
+            });
+            myrpc
+        })
+    }
+
+    #[test]
+    fn balance_test() {
+        let _a = spawn_named_server("balance_a");
+        let _b = spawn_named_server("balance_b");
+        let endpoints: Vec<Address> = vec!["memory:balance_a".parse().unwrap(),
+                                              "memory:balance_b".parse().unwrap(),
+                                              "memory:balance_missing".parse().unwrap()];
+        let mut client = MyRPCClientBuilder::new(endpoints)
+            .balancer(RoundRobin::new())
+            .ejection(1, Duration::from_secs(60))
+            .build();
+        assert_eq!(Ok(String::from("balance_a")), myrpc_call_sync!(client,name,&0;<String>));
+        assert_eq!(Ok(String::from("balance_b")), myrpc_call_sync!(client,name,&0;<String>));
+        // balance_missing 没有服务端，连接失败一次后被摘除
+        assert_eq!(Err(Error::ConnectionFail), myrpc_call_sync!(client,name,&0;<String>));
+        for _ in 0..2 {
+            assert_eq!(Ok(String::from("balance_a")), myrpc_call_sync!(client,name,&0;<String>));
+            assert_eq!(Ok(String::from("balance_b")), myrpc_call_sync!(client,name,&0;<String>));
+        }
+    }
+
+    #[test]
+    fn resolver_test() {
+        let _a = spawn_named_server("resolver_a");
+        let _b = spawn_named_server("resolver_b");
+        let path = env::temp_dir().join("myrpc4rs_resolver_test.toml");
+        fs::write(&path, "port_server = [\"memory:resolver_a\"]\n").unwrap();
+        let mut client = MyRPCClientBuilder::with_resolver("port_server", FileResolver::new(&path))
+            .refresh_interval(Duration::from_millis(50))
+            .build();
+        assert_eq!(Ok(String::from("resolver_a")), myrpc_call_sync!(client,name,&0;<String>));
+
+        // 节点列表变化后，新的请求分配到新的节点上
+        fs::write(&path, "port_server = [\"memory:resolver_b\"]\n").unwrap();
+        let started = Instant::now();
+        while myrpc_call_sync!(client,name,&0;<String>) != Ok(String::from("resolver_b")) {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(Ok(String::from("resolver_b")), myrpc_call_sync!(client,name,&0;<String>));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn retry_test() {
+        let _server = spawn_test_server(|| {
+            let myrpc = MyRPCServer::new("memory:retry_test".parse().unwrap());
+            // 每个函数第一次调用失败，之后成功
+            for name in &["flaky", "idempotent_flaky", "throttled"] {
+                let calls = Cell::new(0);
+                let registration = myrpc.register_function(String::from(*name), move |serializer, _| {
+                    calls.set(calls.get() + 1);
+                    if calls.get() == 1 && *name == "throttled" {
+                        return Err(Error::ResourceExhausted { retry_after_ms: 200 });
+                    }
+                    if calls.get() == 1 {
+                        return Err(Error::ConnectionFail);
+                    }
+                    Ok(serializer.serialize(&calls.get()).unwrap())
+                });
+                if *name != "flaky" {
+                    registration.idempotent();
+                }
+            }
+            myrpc
+        });
+        let endpoints: Vec<Address> = vec!["memory:retry_missing".parse().unwrap(),
+                                              "memory:retry_test".parse().unwrap()];
+        let mut client = MyRPCClientBuilder::new(endpoints)
+            .balancer(RoundRobin::new())
+            .ejection(1, Duration::from_secs(60))
+            .retry_policy(RetryPolicy::new(3).backoff(Duration::from_millis(10), Duration::from_millis(100)))
+            .build();
+        // retry_missing 没有服务端，请求没有发出，非幂等函数也会重试，然后在 retry_test 上第一次调用失败
+        assert_eq!(Err(Error::ConnectionFail), myrpc_call_sync!(client,flaky,&0;<u32>));
+        assert_eq!(Ok(2), myrpc_call_sync!(client,flaky,&0;<u32>));
+        // 幂等函数在服务端返回可重试的错误时自动重试
+        assert_eq!(Ok(2), myrpc_call_sync!(client,idempotent_flaky,&0;<u32>));
+        // 限流时按服务端建议的时间等待，而不是更短的退避时间
+        let mut client = MyRPCClientBuilder::new(vec!["memory:retry_test".parse().unwrap()])
+            .retry_policy(RetryPolicy::new(2)
+                .backoff(Duration::from_millis(10), Duration::from_millis(100))
+                .retry_on(vec![Error::ResourceExhausted { retry_after_ms: 0 }]))
+            .build();
+        let started = Instant::now();
+        assert_eq!(Ok(2), myrpc_call_sync!(client,throttled,&0;<u32>));
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn circuit_breaker_test() {
+        let mut client = MyRPCClientBuilder::new(vec!["memory:circuit_breaker_missing".parse().unwrap()])
+            .ejection(100, Duration::from_secs(60))
+            .circuit_breaker(CircuitBreaker::new()
+                .window(4)
+                .failure_ratio(0.5, 2)
+                .cooldown(Duration::from_millis(200)))
+            .build();
+        // circuit_breaker_missing 没有服务端，两次失败后熔断
+        assert_eq!(Err(Error::ConnectionFail), myrpc_call_sync!(client,port,&0;<u16>));
+        assert_eq!(Err(Error::ConnectionFail), myrpc_call_sync!(client,port,&0;<u16>));
+        assert_eq!(Err(Error::CircuitOpen), myrpc_call_sync!(client,port,&0;<u16>));
+        // 其他函数不受影响
+        assert_eq!(Err(Error::ConnectionFail), myrpc_call_sync!(client,other,&0;<u16>));
+
+        // 冷却后半开，试探调用失败则继续熔断
+        thread::sleep(Duration::from_millis(250));
+        assert_eq!(Err(Error::ConnectionFail), myrpc_call_sync!(client,port,&0;<u16>));
+        assert_eq!(Err(Error::CircuitOpen), myrpc_call_sync!(client,port,&0;<u16>));
+    }
+
+    #[test]
+    fn hedging_test() {
+        // hedging_slow 每次调用耗时 300ms，hedging_fast 立即返回
+        let _servers: Vec<TestServer> = [("hedging_slow", 300u64), ("hedging_fast", 0)].iter().map(|&(name, delay)| {
+            spawn_test_server(move || {
+                let myrpc = MyRPCServer::new(format!("memory:{}", name).parse().unwrap());
+                myrpc.register_function(String::from("name"), move |serializer, _| {
+                    thread::sleep(Duration::from_millis(delay));
+                    Ok(serializer.serialize(&name).unwrap())
+                }).idempotent();
+                myrpc
+            })
+        }).collect();
+        let mut client = MyRPCClientBuilder::new(vec!["memory:hedging_slow".parse().unwrap(), "memory:hedging_fast".parse().unwrap()])
+            .hedging_policy(HedgingPolicy::new(0.9).delay_bounds(Duration::from_millis(1), Duration::from_millis(50)))
+            .build();
+        // 先与两个节点建立连接并获知幂等函数
+        myrpc_call_sync!(client,name,&0;<String>).unwrap();
+        myrpc_call_sync!(client,name,&0;<String>).unwrap();
+
+        let start = Instant::now();
+        for _ in 0..4 {
+            assert_eq!(Ok(String::from("hedging_fast")), myrpc_call_sync!(client,name,&0;<String>));
+        }
+        assert!(start.elapsed() < Duration::from_millis(600));
+    }
+
+    #[test]
+    #[cfg(feature = "tls")]
+    fn tls_test() {
+        use testing::spawn_tcp_server;
+        use tls::{ClientTlsConfig, ServerTlsConfig};
+        use tls::tests::self_signed;
+
+        let (cert, key) = self_signed();
+        let server_tls = ServerTlsConfig::from_pem(cert.as_bytes(), key.as_bytes()).unwrap();
+        let (_server, address) = spawn_tcp_server(move |myrpc| {
+            let myrpc = myrpc.tls(server_tls);
+            myrpc_function!(myrpc,add,a<u32>,b<u32>,{
+                a + b
+            });
+            myrpc
+        });
+        let endpoints: Vec<Address> = vec![address.into()];
+
+        let mut client = MyRPCClientBuilder::new(endpoints.clone())
+            .tls(ClientTlsConfig::from_pem(cert.as_bytes()).unwrap())
+            .build();
+        assert_eq!(Ok(3), myrpc_call_sync!(client,add,&1u32,&2u32;<u32>));
+        let mut client = MyRPCClientBuilder::new(endpoints.clone())
+            .tls(ClientTlsConfig::from_pem(cert.as_bytes()).unwrap().server_name("localhost"))
+            .build();
+        assert_eq!(Ok(3), myrpc_call_sync!(client,add,&1u32,&2u32;<u32>));
+
+        // 不信任服务端证书或不使用 TLS 时无法调用
+        let (other, _) = self_signed();
+        let mut client = MyRPCClientBuilder::new(endpoints.clone())
+            .tls(ClientTlsConfig::from_pem(other.as_bytes()).unwrap())
+            .build();
+        assert_eq!(Err(Error::ConnectionFail), myrpc_call_sync!(client,add,&1u32,&2u32;<u32>));
+        let mut client = MyRPCClientBuilder::new(endpoints).build();
+        assert_eq!(Err(Error::ConnectionFail), myrpc_call_sync!(client,add,&1u32,&2u32;<u32>));
+    }
+
+    #[test]
+    #[cfg(feature = "tls")]
+    fn mtls_test() {
+        use testing::spawn_tcp_server;
+        use tls::{ClientTlsConfig, ServerTlsConfig};
+        use tls::tests::{self_signed, self_signed_for};
+
+        let (cert, key) = self_signed();
+        let (admin_cert, admin_key) = self_signed_for(&["admin.example.com"]);
+        let (guest_cert, guest_key) = self_signed_for(&["guest.example.com"]);
+        let client_ca = format!("{}{}", admin_cert, guest_cert);
+        let server_tls = ServerTlsConfig::from_pem(cert.as_bytes(), key.as_bytes()).unwrap()
+            .client_auth_pem(client_ca.as_bytes()).unwrap();
+        let (_server, address) = spawn_tcp_server(move |myrpc| {
+            let myrpc = myrpc.tls(server_tls);
+            myrpc.register_function_with_context(String::from("whoami"), |serializer, context, _| {
+                let sans = context.identity().map(|identity| identity.sans.clone()).unwrap_or_default();
+                Ok(serializer.serialize(&sans).unwrap())
+            });
+            myrpc_function!(myrpc,reset,a<u32>,{
+                a
+            }).access(|context| context.identity().is_some_and(|identity| identity.has_name("admin.example.com")));
+            myrpc
+        });
+        let endpoints: Vec<Address> = vec![address.into()];
+        let client_tls = ClientTlsConfig::from_pem(cert.as_bytes()).unwrap();
+
+        let mut admin = MyRPCClientBuilder::new(endpoints.clone())
+            .tls(client_tls.clone().identity_pem(admin_cert.as_bytes(), admin_key.as_bytes()).unwrap())
+            .build();
+        assert_eq!(Ok(vec![String::from("admin.example.com")]), myrpc_call_sync!(admin,whoami,&0;<Vec<String>>));
+        assert_eq!(Ok(1), myrpc_call_sync!(admin,reset,&1u32;<u32>));
+
+        let mut guest = MyRPCClientBuilder::new(endpoints.clone())
+            .tls(client_tls.clone().identity_pem(guest_cert.as_bytes(), guest_key.as_bytes()).unwrap())
+            .build();
+        assert_eq!(Ok(vec![String::from("guest.example.com")]), myrpc_call_sync!(guest,whoami,&0;<Vec<String>>));
+        assert_eq!(Err(Error::PermissionDenied), myrpc_call_sync!(guest,reset,&1u32;<u32>));
+
+        // 没有客户端证书时握手失败
+        let mut anonymous = MyRPCClientBuilder::new(endpoints).tls(client_tls).build();
+        assert_eq!(Err(Error::ConnectionFail), myrpc_call_sync!(anonymous,whoami,&0;<Vec<String>>));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn unix_socket_test() {
+        let path = env::temp_dir().join("myrpc4rs_unix_socket_test.sock");
+        let address = Address::Unix(path.clone());
+        let _ = fs::remove_file(&path);
+        let _server = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new(address);
+            myrpc_function!(myrpc,add,a<u32>,b<u32>,{
+                a + b
+            });
+            myrpc
+        });
+        // 服务端开始监听时才创建套接字文件
+        while !path.exists() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mut client = MyRPCClient::new(format!("unix:{}", path.display()).parse().unwrap());
+        assert_eq!(Ok(3), myrpc_call_sync!(client,add,&1u32,&2u32;<u32>));
+        let mut client = MyRPCClientBuilder::new(vec![Address::Unix(path.with_extension("missing"))]).build();
+        assert_eq!(Err(Error::ConnectionFail), myrpc_call_sync!(client,add,&1u32,&2u32;<u32>));
+    }
+
+    /// 统计建立的连接数的传输层
+    struct CountingTransport(Arc<AtomicUsize>);
+
+    impl Transport for CountingTransport {
+        fn listen(&self, address: &Address, handle: &Handle) -> io::Result<Incoming> {
+            let count = Arc::clone(&self.0);
+            let incoming = MemoryTransport.listen(address, handle)?
+                .inspect(move |_| { count.fetch_add(1, Ordering::SeqCst); });
+            Ok(Box::new(incoming))
+        }
+
+        fn connect(&self, address: &Address, handle: &Handle) -> Connecting {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            MemoryTransport.connect(address, handle)
+        }
+    }
+
+    #[test]
+    fn transport_test() {
+        let address = Address::Memory(String::from("transport_test"));
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let connected = Arc::new(AtomicUsize::new(0));
+        let server_address = address.clone();
+        let server_accepted = Arc::clone(&accepted);
+        let _server = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new(server_address).transport(CountingTransport(server_accepted));
+            myrpc_function!(myrpc,add,a<u32>,b<u32>,{
+                a + b
+            });
+            myrpc
+        });
+        let mut client = MyRPCClientBuilder::new(vec![address])
+            .transport(CountingTransport(Arc::clone(&connected)))
+            .build();
+        assert_eq!(Ok(3), myrpc_call_sync!(client,add,&1u32,&2u32;<u32>));
+        assert_eq!(1, connected.load(Ordering::SeqCst));
+        assert_eq!(1, accepted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn authentication_test() {
+        use auth::{BearerToken, StaticTokenAuthenticator};
+
+        let _server = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new("memory:authentication_test".parse().unwrap())
+                .authenticator(StaticTokenAuthenticator::new().token("s3cr3t", "path_client"));
+            myrpc.register_function_with_context(String::from("whoami"), |serializer, context, _| {
+                let name = context.identity().and_then(|identity| identity.common_name()).map(String::from);
+                Ok(serializer.serialize(&name).unwrap())
+            });
+            myrpc
+        });
+        let mut client = MyRPCClientBuilder::new(vec!["memory:authentication_test".parse().unwrap()])
+            .credentials(BearerToken(String::from("s3cr3t")))
+            .build();
+        assert_eq!(Ok(Some(String::from("path_client"))), myrpc_call_sync!(client,whoami,&0;<Option<String>>));
+        let mut client = MyRPCClientBuilder::new(vec!["memory:authentication_test".parse().unwrap()])
+            .credentials(BearerToken(String::from("guess")))
+            .build();
+        assert_eq!(Err(Error::Unauthenticated), myrpc_call_sync!(client,whoami,&0;<Option<String>>));
+        let mut client = MyRPCClient::new("memory:authentication_test".parse().unwrap());
+        assert_eq!(Err(Error::Unauthenticated), myrpc_call_sync!(client,whoami,&0;<Option<String>>));
+    }
+
+    #[test]
+    fn context_test() {
+        use context::TIMEOUT;
+
+        let _backend = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new("memory:context_backend".parse().unwrap());
+            myrpc.register_function_with_context(String::from("trace"), |serializer, context, _| {
+                let trace = context.metadata().get("trace").cloned();
+                Ok(serializer.serialize(&(trace, context.deadline().is_some())).unwrap())
+            });
+            myrpc_function!(myrpc,sleep,millis<u64>,{
+                thread::sleep(Duration::from_millis(millis));
+                millis
+            });
+            myrpc
+        });
+        let _frontend = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new("memory:context_frontend".parse().unwrap());
+            let mut client = MyRPCClient::new("memory:context_backend".parse().unwrap());
+            // 处理函数发出的调用自动带上当前请求的元数据和截止时间
+            myrpc.register_function(String::from("forward"), move |serializer, _| {
+                let result: (Option<String>, bool) = myrpc_call_sync!(client,trace,&0;<(Option<String>, bool)>)?;
+                Ok(serializer.serialize(&result).unwrap())
+            });
+            myrpc
+        });
+
+        let mut client = MyRPCClient::new("memory:context_frontend".parse().unwrap());
+        let params = vec![client.get_serializer().serialize(&0).unwrap()];
+        let response = client.call(String::from("forward"), params.clone())
+            .header("trace", "abc")
+            .timeout(Duration::from_secs(5))
+            .sync();
+        assert_eq!((Some(String::from("abc")), true), client.get_serializer().deserialize(&response.result.unwrap()).unwrap());
+        let response = client.call(String::from("forward"), params).sync();
+        assert_eq!((None::<String>, false), client.get_serializer().deserialize(&response.result.unwrap()).unwrap());
+
+        let mut client = MyRPCClient::new("memory:context_backend".parse().unwrap());
+        let params = vec![client.get_serializer().serialize(&200u64).unwrap()];
+        let started = Instant::now();
+        let response = client.call(String::from("sleep"), params.clone()).timeout(Duration::from_millis(50)).sync();
+        assert_eq!(Err(Error::DeadlineExceeded), response.result);
+        assert!(started.elapsed() < Duration::from_millis(200));
+        // 超过上限的超时时间视为没有截止时间，客户端和服务端都不会 panic
+        let response = client.call(String::from("sleep"), vec![client.get_serializer().serialize(&0u64).unwrap()])
+            .header(TIMEOUT, &u64::MAX.to_string())
+            .sync();
+        assert_eq!(0, client.get_serializer().deserialize::<u64>(&response.result.unwrap()).unwrap());
+        // 到达服务端时已经超时的请求不再执行
+        let response = client.call(String::from("sleep"), params).timeout(Duration::from_secs(0)).sync();
+        assert_eq!(Err(Error::DeadlineExceeded), response.result);
+        // 截止时间由 NET 层线程执行：异步调用同样超时，超时的调用不会使客户端 drop 时等待服务端
+        let params = vec![client.get_serializer().serialize(&500u64).unwrap()];
+        let (sender, receiver) = mpsc::channel();
+        client.call(String::from("sleep"), params).timeout(Duration::from_millis(50)).async(move |_, response| {
+            let _ = sender.send(response.result.clone());
+        });
+        assert_eq!(Ok(Err(Error::DeadlineExceeded)), receiver.recv_timeout(Duration::from_millis(300)));
+        let started = Instant::now();
+        drop(client);
+        assert!(started.elapsed() < Duration::from_millis(300));
+    }
+
+    #[test]
+    fn heartbeat_test() {
+        use keepalive::Heartbeat;
+
+        let _server = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new("memory:heartbeat_test".parse().unwrap());
+            // 服务端线程阻塞期间无法回复心跳
+            myrpc_function!(myrpc,sleep,millis<u64>,{
+                thread::sleep(Duration::from_millis(millis));
+                millis
+            });
+            myrpc
+        });
+        let mut client = MyRPCClientBuilder::new(vec!["memory:heartbeat_test".parse().unwrap()])
+            .heartbeat(Heartbeat::new(Duration::from_millis(50)).timeout(Duration::from_millis(200)))
+            .build();
+        // 处理时间超过心跳间隔、但在超时之内的调用不受影响
+        assert_eq!(Ok(100), myrpc_call_sync!(client,sleep,&100u64;<u64>));
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(Ok(0), myrpc_call_sync!(client,sleep,&0u64;<u64>));
+        let started = Instant::now();
+        assert_eq!(Err(Error::ConnectionFail), myrpc_call_sync!(client,sleep,&1000u64;<u64>));
+        assert!(started.elapsed() < Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn backpressure_test() {
+        use client::Backpressure;
+        use std::net::TcpListener;
+
+        // 接受连接但从不读取的服务端，在客户端之前 drop，在途调用随之失败，客户端才能停止
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = MyRPCClientBuilder::new(vec![Address::Tcp(listener.local_addr().unwrap())])
+            .write_buffer_limit(1 << 16)
+            .queue_capacity(2)
+            .backpressure(Backpressure::Reject)
+            .build();
+        let (sender, receiver) = mpsc::channel();
+        let rejected = |receiver: &mpsc::Receiver<Result<Vec<u8>, Error>>| {
+            receiver.try_iter().any(|result| result == Err(Error::ResourceExhausted { retry_after_ms: 0 }))
+        };
+        let call = |client: &mut MyRPCClient| {
+            let sender = sender.clone();
+            client.call(String::from("echo"), vec![vec![0u8; 1 << 20]]).async(move |_, response| {
+                let _ = sender.send(response.result.clone());
+            });
+        };
+        // 队列暂时满了的调用被拒绝；连接的缓冲区写满后，NET 层线程等待一段时间也不再取出调用
+        let (mut calls, mut waits) = (0, 0);
+        loop {
+            call(&mut client);
+            calls += 1;
+            assert!(calls < 10000);
+            if rejected(&receiver) {
+                waits += 1;
+                assert!(waits < 50);
+                thread::sleep(Duration::from_millis(100));
+                call(&mut client);
+                if rejected(&receiver) {
+                    break;
+                }
+            }
+        }
+        drop(listener);
+    }
+
+    #[test]
+    fn stream_test() {
+        use futures::{stream, Stream};
+        use limit::Limits;
+        use stream::channel;
+
+        let _server = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new("memory:stream_test".parse().unwrap())
+                .limits(Limits::new().max_concurrent("progress", 1));
+            // 在另一个线程中逐个产出值
+            myrpc.register_stream_function(String::from("progress"), |format, _, params| {
+                let steps: u32 = format.deserialize(&params[0]).map_err(|_| Error::ParamDeserializeFail)?;
+                let (mut sender, stream) = channel(format, 1);
+                thread::spawn(move || {
+                    for step in 0..steps {
+                        thread::sleep(Duration::from_millis(20));
+                        if sender.send(&step).is_err() {
+                            break;
+                        }
+                    }
+                });
+                Ok(stream)
+            });
+            myrpc.register_stream_function(String::from("failing"), |format, _, _| {
+                let values = vec![Ok(format.serialize(&1u32).unwrap()), Err(Error::PermissionDenied)];
+                Ok(Box::new(stream::iter_result(values)))
+            });
+            myrpc_function!(myrpc,add,a<u32>,b<u32>,{
+                a + b
+            });
+            myrpc
         });
+        let mut client = MyRPCClient::new("memory:stream_test".parse().unwrap());
+        let params = vec![client.get_serializer().serialize(&5u32).unwrap()];
+        let mut progress = client.call(String::from("progress"), params.clone()).stream::<u32>().wait();
+        assert_eq!(Some(Ok(0)), progress.next());
+        // 流结束之前一直占用并发名额
+        let rejected: Vec<Result<u32, Error>> = client.call(String::from("progress"), params.clone()).stream().wait().collect();
+        assert_eq!(vec![Err(Error::ResourceExhausted { retry_after_ms: 0 })], rejected);
+        assert_eq!(Ok(vec![1, 2, 3, 4]), progress.collect::<Result<Vec<u32>, Error>>());
+        let progress: Result<Vec<u32>, Error> = client.call(String::from("progress"), params).stream().wait().collect();
+        assert_eq!(Ok(vec![0, 1, 2, 3, 4]), progress);
 
-        let resp = myrpc_call_sync!(client,test1,&16,&24;<u32>);
-        println!("{:?}", resp);
+        let failing: Vec<Result<u32, Error>> = client.call(String::from("failing"), vec![]).stream().wait().collect();
+        assert_eq!(vec![Ok(1), Err(Error::PermissionDenied)], failing);
+        // 普通函数的返回值作为唯一的值
+        let params = vec![client.get_serializer().serialize(&1u32).unwrap(), client.get_serializer().serialize(&2u32).unwrap()];
+        let sum: Vec<Result<u32, Error>> = client.call(String::from("add"), params).stream().wait().collect();
+        assert_eq!(vec![Ok(3)], sum);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn bidi_test() {
+        use common::StreamFrame;
+        use stream::{channel, RequestSink, Window, DEFAULT_WINDOW};
+
+        let produced = Arc::new(AtomicUsize::new(0));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let (counter, finished) = (Arc::clone(&produced), Arc::clone(&stopped));
+        let _server = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new("memory:bidi_test".parse().unwrap());
+            // 客户端流式：返回收到的字节数
+            myrpc.register_bidi_function(String::from("upload"), |format, _, _, chunks| {
+                let format = *format;
+                let total = chunks.fold(0usize, move |total, chunk| {
+                    format.deserialize::<Vec<u8>>(&chunk).map(|chunk| total + chunk.len()).map_err(|_| Error::ParamDeserializeFail)
+                });
+                Ok(Box::new(total.map(move |total| format.serialize(&total).unwrap()).into_stream()))
+            });
+            myrpc.register_bidi_function(String::from("reroute"), |format, _, _, positions| {
+                let format = *format;
+                Ok(Box::new(positions.map(move |position| {
+                    let position: u32 = format.deserialize(&position).unwrap();
+                    format.serialize(&(position * 2)).unwrap()
+                })))
+            });
+            myrpc.register_stream_function(String::from("endless"), move |format, _, _| {
+                let (mut sender, stream) = channel(format, 1);
+                let (counter, finished) = (Arc::clone(&counter), Arc::clone(&finished));
+                thread::spawn(move || {
+                    while sender.send(&0u32).is_ok() {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                    }
+                    finished.store(true, Ordering::SeqCst);
+                });
+                Ok(stream)
+            });
+            // 从不读取消息
+            myrpc.register_bidi_function(String::from("hold"), |_, _, _, messages| {
+                Ok(Box::new(futures::stream::poll_fn(move || {
+                    let _ = &messages;
+                    Ok(futures::Async::NotReady)
+                })))
+            });
+            myrpc
+        });
+        let mut client = MyRPCClient::new("memory:bidi_test".parse().unwrap());
+
+        // 消息数超过额度，服务端取走消息后归还
+        let (mut chunks, total) = client.call(String::from("upload"), vec![]).bidi::<usize>();
+        for _ in 0..100 {
+            chunks.send(&vec![0u8; 1000]).unwrap();
+        }
+        chunks.finish();
+        assert_eq!(Ok(vec![100000]), total.wait().collect::<Result<Vec<usize>, Error>>());
+
+        let (mut positions, reroutes) = client.call(String::from("reroute"), vec![]).bidi::<u32>();
+        let mut reroutes = reroutes.wait();
+        for position in 0..40u32 {
+            positions.send(&position).unwrap();
+            assert_eq!(Some(Ok(position * 2)), reroutes.next());
+        }
+        drop(positions);
+        assert_eq!(None, reroutes.next());
+
+        // 客户端不读取时，服务端最多多发出一个额度的值；丢弃流后服务端停止产出
+        let mut endless = client.call(String::from("endless"), vec![]).stream::<u32>().wait();
+        assert_eq!(Some(Ok(0)), endless.next());
+        thread::sleep(Duration::from_millis(200));
+        assert!(produced.load(Ordering::SeqCst) < 24);
+        drop(endless);
+        let started = Instant::now();
+        while !stopped.load(Ordering::SeqCst) {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // 不遵守额度的客户端使调用以错误结束，服务端不会无限制地缓存消息
+        let call = client.call(String::from("hold"), vec![]);
+        let mut request = call.request.clone();
+        request.stream = Some(StreamFrame { sequence: 0, end: false });
+        let (_sink, held) = call.bidi::<u32>();
+        let mut greedy = RequestSink::new(&request, *client.get_serializer(), Rc::clone(&client.sender), Arc::new(Window::new(u32::MAX)));
+        for _ in 0..DEFAULT_WINDOW * 2 {
+            greedy.send(&0u8).unwrap();
+        }
+        assert_eq!(vec![Err(Error::ResourceExhausted { retry_after_ms: 0 })], held.wait().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn subscribe_test() {
+        use limit::Limits;
+        use pubsub;
+
+        let server = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new("memory:subscribe_test".parse().unwrap())
+                .limits(Limits::new().max_concurrent(pubsub::SUBSCRIBE, 2));
+            let publisher = myrpc.publisher();
+            myrpc.register_function(String::from("report"), move |format, params| {
+                let (road, busyness): (u32, u32) = format.deserialize(&params[0]).unwrap();
+                publisher.publish(&format!("road/{}", road), &busyness);
+                Ok(format.serialize(&()).unwrap())
+            });
+            myrpc
+        });
+        let publisher = server.publisher();
+        let mut client = MyRPCClient::new("memory:subscribe_test".parse().unwrap());
+        let serializer = *client.get_serializer();
+
+        // 同一连接上的请求按顺序处理，订阅先于发布生效
+        let mut road = client.subscribe::<u32>("road/1").wait();
+        for (id, busyness) in [(2u32, 5u32), (1, 7)] {
+            let params = vec![serializer.serialize(&(id, busyness)).unwrap()];
+            assert!(client.call(String::from("report"), params).sync().result.is_ok());
+        }
+        assert_eq!(Some(Ok(7)), road.next());
+        assert_eq!(1, publisher.subscribers("road/1"));
+        assert_eq!(1, publisher.publish("road/1", &9u32));
+        assert_eq!(Some(Ok(9)), road.next());
+
+        // 订阅占用并发名额，取消订阅后归还
+        let other = client.subscribe::<u32>("road/2");
+        let mut rejected = client.subscribe::<u32>("road/4").wait();
+        assert_eq!(Some(Err(Error::ResourceExhausted { retry_after_ms: 0 })), rejected.next());
+
+        // 取消订阅之后不再推送，从未发布过的主题也不会留下订阅者
+        drop(road);
+        drop(other);
+        let started = Instant::now();
+        while publisher.subscribers("road/1") + publisher.subscribers("road/2") > 0 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(0, publisher.publish("road/1", &0u32));
+
+        // 不读取的订阅者超过缓冲之后以错误结束
+        let slow = client.subscribe::<u32>("road/3").wait();
+        while publisher.subscribers("road/3") == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        for busyness in 0..200u32 {
+            publisher.publish("road/3", &busyness);
+        }
+        let mut received = slow.collect::<Vec<_>>();
+        assert_eq!(Some(Err(Error::ResourceExhausted { retry_after_ms: 0 })), received.pop());
+        assert!(!received.is_empty() && received.len() < 200);
+        assert!(received.iter().all(Result::is_ok));
+        assert_eq!(0, publisher.subscribers("road/3"));
+    }
+
+    #[test]
+    fn one_way_test() {
+        let _server = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new("memory:one_way_test".parse().unwrap());
+            let busyness = Rc::new(Cell::new(0u64));
+            let reported = Rc::clone(&busyness);
+            myrpc.register_function(String::from("report"), move |serializer, params| {
+                let value: u64 = serializer.deserialize(&params[0]).map_err(|_| Error::ParamDeserializeFail)?;
+                reported.set(reported.get() + value);
+                Ok(serializer.serialize(&()).unwrap())
+            });
+            myrpc.register_function(String::from("busyness"), move |serializer, _| {
+                Ok(serializer.serialize(&busyness.get()).unwrap())
+            });
+            myrpc
+        });
+        let mut client = MyRPCClient::new("memory:one_way_test".parse().unwrap());
+        for value in 1..=10u64 {
+            let params = vec![client.get_serializer().serialize(&value).unwrap()];
+            assert_eq!(Ok(()), client.call(String::from("report"), params).one_way());
+        }
+        // 失败的单向调用不影响同一连接上之后的调用
+        assert_eq!(Ok(()), client.call(String::from("missing"), vec![]).one_way());
+        // 同一连接上的请求按顺序处理，之前的单向调用都已经执行
+        let response = client.call(String::from("busyness"), vec![]).sync();
+        assert_eq!(55, client.get_serializer().deserialize::<u64>(&response.result.unwrap()).unwrap());
+    }
+
+    #[test]
+    fn interceptor_test() {
+        use common::{Request, Response};
+        use interceptor::ClientInterceptor;
+        use serialization::BincodeSerializer;
+
+        struct Tracing(Arc<AtomicUsize>);
+
+        impl ClientInterceptor for Tracing {
+            fn before(&self, request: &mut Request) -> Option<Response> {
+                request.headers.insert(String::from("trace"), String::from("abc"));
+                None
+            }
+
+            fn after(&self, _: &Request, _: &mut Response, _: Duration) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        /// `offline` 不发送，`length` 的结果被替换
+        struct Stub;
+
+        impl ClientInterceptor for Stub {
+            fn before(&self, request: &mut Request) -> Option<Response> {
+                match request.name.as_str() {
+                    "offline" => Some(Response::err(request.clone(), Error::ConnectionFail)),
+                    _ => None,
+                }
+            }
+
+            fn after(&self, request: &Request, response: &mut Response, _: Duration) {
+                if request.name == "length" {
+                    response.result = Ok(BincodeSerializer::new().serialize(&0usize).unwrap());
+                }
+            }
+        }
+
+        let _server = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new("memory:client_interceptor_test".parse().unwrap());
+            myrpc.register_function_with_context(String::from("trace"), |serializer, context, _| {
+                Ok(serializer.serialize(&context.metadata().get("trace").cloned()).unwrap())
+            });
+            myrpc.register_function_with_context(String::from("length"), |serializer, context, _| {
+                Ok(serializer.serialize(&context.metadata().len()).unwrap())
+            });
+            myrpc
+        });
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut client = MyRPCClientBuilder::new(vec!["memory:client_interceptor_test".parse().unwrap()])
+            .interceptor(Tracing(Arc::clone(&calls)))
+            .interceptor(Stub)
+            .build();
+        assert_eq!(Ok(Some(String::from("abc"))), myrpc_call_sync!(client,trace,&0;<Option<String>>));
+        assert_eq!(Ok(0usize), myrpc_call_sync!(client,length,&0;<usize>));
+        assert_eq!(Err(Error::ConnectionFail), myrpc_call_sync!(client,offline,&0;<u32>));
+        let (result_sender, result_receiver) = mpsc::channel();
+        client.call(String::from("trace"), vec![]).async(move |_, response| {
+            result_sender.send(response.result.is_ok()).unwrap();
+        });
+        assert!(result_receiver.recv().unwrap());
+        assert_eq!(4, calls.load(Ordering::SeqCst));
+    }
+
+    /// 在 `corrupt` 置位后翻转收到的下一段数据的最后一个字节
+    struct CorruptingTransport(Arc<AtomicBool>);
+
+    struct CorruptingSocket {
+        inner: Box<dyn Socket>,
+        corrupt: Arc<AtomicBool>,
+    }
+
+    impl io::Read for CorruptingSocket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            if n > 0 && self.corrupt.swap(false, Ordering::SeqCst) {
+                buf[n - 1] ^= 0x01;
+            }
+            Ok(n)
+        }
+    }
+
+    impl io::Write for CorruptingSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    impl AsyncRead for CorruptingSocket {}
+
+    impl AsyncWrite for CorruptingSocket {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            self.inner.shutdown()
+        }
+    }
+
+    impl Socket for CorruptingSocket {}
+
+    impl Transport for CorruptingTransport {
+        fn listen(&self, address: &Address, handle: &Handle) -> io::Result<Incoming> {
+            MemoryTransport.listen(address, handle)
+        }
+
+        fn connect(&self, address: &Address, handle: &Handle) -> Connecting {
+            let corrupt = Arc::clone(&self.0);
+            Box::new(MemoryTransport.connect(address, handle).map(move |inner| {
+                Box::new(CorruptingSocket { inner, corrupt }) as Box<dyn Socket>
+            }))
+        }
+    }
+
+    #[test]
+    fn checksum_test() {
+        let address = Address::Memory(String::from("checksum_test"));
+        let corrupt = Arc::new(AtomicBool::new(false));
+        let server_address = address.clone();
+        let _server = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new(server_address);
+            myrpc_function!(myrpc,add,a<u32>,b<u32>,{
+                a + b
+            });
+            myrpc
+        });
+        let mut client = MyRPCClientBuilder::new(vec![address])
+            .transport(CorruptingTransport(Arc::clone(&corrupt)))
+            .checksum(true)
+            .build();
+        assert_eq!(Ok(3), myrpc_call_sync!(client,add,&1u32,&2u32;<u32>));
+        corrupt.store(true, Ordering::SeqCst);
+        assert_eq!(Err(Error::CorruptFrame), myrpc_call_sync!(client,add,&1u32,&2u32;<u32>));
+        // 损坏的连接已关闭，下一次调用重新连接
+        assert_eq!(Ok(3), myrpc_call_sync!(client,add,&1u32,&2u32;<u32>));
+    }
+
+    #[test]
+    #[cfg(feature = "websocket")]
+    fn websocket_test() {
+        use std::net::TcpStream;
+        use testing::LocalTransport;
+        use transport::DefaultTransport;
+        use tungstenite::{self, Message};
+        use ws::WsTransport;
+
+        let (sender, receiver) = mpsc::channel();
+        let _server = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new(Address::Memory(String::from("websocket_test")))
+                .listen("127.0.0.1:0".parse().unwrap(), WsTransport::new(LocalTransport(sender)).path("/rpc"));
+            myrpc_function!(myrpc,add,a<u32>,b<u32>,{
+                a + b
+            });
+            myrpc
+        });
+        let mut client = MyRPCClientBuilder::new(vec![Address::Memory(String::from("websocket_test"))]).build();
+        assert_eq!(Ok(3), myrpc_call_sync!(client,add,&1u32,&2u32;<u32>));
+        let address = receiver.recv().unwrap();
+        let mut client = MyRPCClientBuilder::new(vec![address.into()])
+            .transport(WsTransport::new(DefaultTransport).path("/rpc"))
+            .build();
+        assert_eq!(Ok(3), myrpc_call_sync!(client,add,&1u32,&2u32;<u32>));
+        // 浏览器不指定子协议时使用 JSON
+        let stream = TcpStream::connect(address).unwrap();
+        let (mut socket, _) = tungstenite::client(format!("ws://{}/rpc", address), stream).unwrap();
+        socket.send(Message::Binary(br#"{"id":7,"name":"add","params":[1,2]}"#.to_vec())).unwrap();
+        assert_eq!(Message::Binary(br#"{"id":7,"name":"add","result":{"Ok":3}}"#.to_vec()), socket.read().unwrap());
+        socket.send(Message::Text(String::from(r#"{"id":8,"name":"sub","params":[]}"#))).unwrap();
+        assert_eq!(Message::Binary(br#"{"id":8,"name":"sub","result":{"Err":"FunctionNotFound"}}"#.to_vec()), socket.read().unwrap());
+        let stream = TcpStream::connect(address).unwrap();
+        assert!(tungstenite::client(format!("ws://{}/other", address), stream).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compression_test() {
+        use compression::Compression;
+
+        let _server = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new("memory:compression_test".parse().unwrap())
+                .compression(Compression::lz4().threshold(256));
+            myrpc_function!(myrpc,repeat,text<String>,count<u32>,{
+                text.repeat(count as usize)
+            });
+            myrpc
+        });
+        let expected = "route".repeat(2000);
+        let mut client = MyRPCClientBuilder::new(vec!["memory:compression_test".parse().unwrap()])
+            .compression(Compression::lz4().threshold(256))
+            .build();
+        assert_eq!(Ok(expected.clone()), myrpc_call_sync!(client,repeat,&expected,&1u32;<String>));
+        assert_eq!(Ok(String::from("aaa")), myrpc_call_sync!(client,repeat,&String::from("a"),&3u32;<String>));
+        // 客户端没有启用压缩时，服务端也不压缩响应
+        let mut client = MyRPCClient::new("memory:compression_test".parse().unwrap());
+        assert_eq!(Ok(expected.clone()), myrpc_call_sync!(client,repeat,&String::from("route"),&2000u32;<String>));
+    }
+}
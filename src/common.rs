@@ -1,11 +1,109 @@
+use auth::Headers;
+use compression::Algorithm;
 use error::Error;
-use std::u32;
+use serialization::{BincodeSerializer, Format, Serializer};
+use serde_json::{self, Value};
+use std::error;
+
+type CodecResult<T> = ::std::result::Result<T, Box<dyn error::Error>>;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Request {
     pub id: u32,
     pub name: String,
     pub params: Vec<Vec<u8>>,
+    /// 凭据等元数据
+    pub headers: Headers,
+    /// 单向调用，服务端不返回响应
+    pub one_way: bool,
+    /// 流式调用的帧：第一帧开始调用，`end` 为 false 表示客户端随后还会发送消息，
+    /// 之后每一帧的参数是一个消息，最后以 `end` 为 true 的帧结束
+    pub stream: Option<StreamFrame>,
+}
+
+/// 连接建立后客户端发送的第一个请求，用连接的初始格式编码，之后双方改用协商的格式
+pub const HANDSHAKE: &str = "$handshake";
+/// 客户端内部的握手请求使用的 id，用户请求的 id 在这个值之前回绕
+pub const HANDSHAKE_ID: u32 = u32::MAX - 2;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Handshake {
+    pub format: Format,
+    /// 客户端提出的压缩算法，服务端在响应中返回接受的算法
+    pub compression: Option<Algorithm>,
+    /// 双方的帧都带 CRC32C 校验和
+    pub checksum: bool,
+}
+
+/// JSON 格式的请求，参数直接是 JSON 值而不是字节数组：
+/// `{"id":1,"name":"add","params":[1,2]}`
+#[derive(Serialize, Deserialize)]
+struct JsonRequest {
+    id: u32,
+    name: String,
+    params: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Headers::is_empty")]
+    headers: Headers,
+    #[serde(default, skip_serializing_if = "is_false")]
+    one_way: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stream: Option<StreamFrame>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// `{"id":1,"name":"add","result":{"Ok":3}}`，失败时为 `{"Err":"FunctionNotFound"}`。
+/// 流式函数的每一帧带 `"stream":{"sequence":0,"end":false}`
+#[derive(Serialize, Deserialize)]
+struct JsonResponse {
+    id: u32,
+    name: String,
+    result: Result<Value, Error>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stream: Option<StreamFrame>,
+}
+
+impl Request {
+    pub fn encode(&self, format: Format) -> CodecResult<Vec<u8>> {
+        match format {
+            Format::Bincode => BincodeSerializer.serialize(self),
+            Format::Json => {
+                let params = self.params.iter()
+                    .map(|param| serde_json::from_slice(param))
+                    .collect::<Result<Vec<Value>, _>>()?;
+                Ok(serde_json::to_vec(&JsonRequest {
+                    id: self.id,
+                    name: self.name.clone(),
+                    params,
+                    headers: self.headers.clone(),
+                    one_way: self.one_way,
+                    stream: self.stream,
+                })?)
+            }
+        }
+    }
+
+    pub fn decode(format: Format, bytes: &[u8]) -> CodecResult<Self> {
+        match format {
+            Format::Bincode => BincodeSerializer.deserialize(bytes),
+            Format::Json => {
+                let request: JsonRequest = serde_json::from_slice(bytes)?;
+                let params = request.params.iter()
+                    .map(serde_json::to_vec)
+                    .collect::<Result<Vec<Vec<u8>>, _>>()?;
+                Ok(Self {
+                    id: request.id,
+                    name: request.name,
+                    params,
+                    headers: request.headers,
+                    one_way: request.one_way,
+                    stream: request.stream,
+                })
+            }
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -13,6 +111,17 @@ pub struct Response {
     pub id: u32,
     pub name: String,
     pub result: Result<Vec<u8>, Error>,
+    /// 流式函数的响应帧，普通函数的响应为 None
+    pub stream: Option<StreamFrame>,
+}
+
+/// 流式调用中一帧的位置，流以请求的 id 区分，两个方向各自编号。
+/// 响应的中间帧的 `result` 是一个值；最后一帧 `end` 为 true，成功时不带值，失败时带错误
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct StreamFrame {
+    /// 从 0 开始按帧递增
+    pub sequence: u32,
+    pub end: bool,
 }
 
 impl Response {
@@ -21,6 +130,7 @@ impl Response {
             id: request.id,
             name: request.name,
             result: Err(error),
+            stream: None,
         }
     }
     pub fn err_unknow_request(error: Error) -> Self {
@@ -28,6 +138,7 @@ impl Response {
             id: u32::MAX,
             name: String::new(),
             result: Err(error),
+            stream: None,
         }
     }
     pub fn from(request: Request, result: Vec<u8>) -> Self {
@@ -35,12 +146,89 @@ impl Response {
             id: request.id,
             name: request.name,
             result: Ok(result),
+            stream: None,
+        }
+    }
+
+    /// 流的结束帧或中途出错时也结束流的帧
+    pub fn is_end(&self) -> bool {
+        self.stream.is_none_or(|frame| frame.end)
+    }
+
+    pub fn encode(&self, format: Format) -> CodecResult<Vec<u8>> {
+        match format {
+            Format::Bincode => BincodeSerializer.serialize(self),
+            Format::Json => {
+                let result = match self.result {
+                    Ok(ref bytes) => Ok(serde_json::from_slice(bytes)?),
+                    Err(ref err) => Err(err.clone()),
+                };
+                Ok(serde_json::to_vec(&JsonResponse { id: self.id, name: self.name.clone(), result, stream: self.stream })?)
+            }
+        }
+    }
+
+    pub fn decode(format: Format, bytes: &[u8]) -> CodecResult<Self> {
+        match format {
+            Format::Bincode => BincodeSerializer.deserialize(bytes),
+            Format::Json => {
+                let response: JsonResponse = serde_json::from_slice(bytes)?;
+                let result = match response.result {
+                    Ok(value) => Ok(serde_json::to_vec(&value)?),
+                    Err(err) => Err(err),
+                };
+                Ok(Self { id: response.id, name: response.name, result, stream: response.stream })
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use common::{Request, Response, StreamFrame};
+    use error::Error;
+    use serialization::Format;
+
+    #[test]
+    fn command_test() {
+        // 参数和结果是合法的 JSON，两种格式都能表示
+        let mut request = Request {
+            id: 7,
+            name: String::from("add"),
+            params: vec![b"1".to_vec(), br#"[2,"a"]"#.to_vec()],
+            headers: Default::default(),
+            one_way: true,
+            stream: Some(StreamFrame { sequence: 1, end: true }),
+        };
+        request.headers.insert(String::from("authorization"), String::from("Bearer t"));
+        request.headers.insert(String::from("timeout"), String::from("100"));
+        let response = Response { stream: Some(StreamFrame { sequence: 3, end: false }), ..Response::from(request.clone(), b"3".to_vec()) };
+        let error = Response::err(request.clone(), Error::ResourceExhausted { retry_after_ms: 5 });
+        for &format in &[Format::Bincode, Format::Json] {
+            assert_eq!(request, Request::decode(format, &request.encode(format).unwrap()).unwrap());
+            assert_eq!(response, Response::decode(format, &response.encode(format).unwrap()).unwrap());
+            assert_eq!(error, Response::decode(format, &error.encode(format).unwrap()).unwrap());
+            // 默认值的字段同样保留
+            let plain = Request { headers: Default::default(), one_way: false, stream: None, ..request.clone() };
+            assert_eq!(plain, Request::decode(format, &plain.encode(format).unwrap()).unwrap());
+        }
+    }
+
     #[test]
-    fn command_test() {}
+    fn json_test() {
+        let request = Request::decode(Format::Json, br#"{"id":1,"name":"add","params":[1,[2,3]]}"#).unwrap();
+        assert_eq!(vec![b"1".to_vec(), b"[2,3]".to_vec()], request.params);
+        let response = Response::from(request.clone(), b"3".to_vec());
+        assert_eq!(br#"{"id":1,"name":"add","result":{"Ok":3}}"#.to_vec(), response.encode(Format::Json).unwrap());
+        assert!(Request::decode(Format::Json, b"[1,2]").is_err());
+        assert_eq!(request, Request::decode(Format::Json, &request.encode(Format::Json).unwrap()).unwrap());
+        let request = Request::decode(Format::Json, br#"{"id":2,"name":"add","params":[],"headers":{"authorization":"Bearer t"}}"#).unwrap();
+        assert_eq!(Some("Bearer t"), request.headers.get("authorization").map(String::as_str));
+        let bytes = Response::err(request, Error::PermissionDenied).encode(Format::Json).unwrap();
+        assert_eq!(Err(Error::PermissionDenied), Response::decode(Format::Json, &bytes).unwrap().result);
+        let frame = Response { stream: Some(StreamFrame { sequence: 2, end: false }), ..response };
+        let bytes = frame.encode(Format::Json).unwrap();
+        assert_eq!(br#"{"id":1,"name":"add","result":{"Ok":3},"stream":{"sequence":2,"end":false}}"#.to_vec(), bytes);
+        assert_eq!(frame, Response::decode(Format::Json, &bytes).unwrap());
+    }
 }
\ No newline at end of file
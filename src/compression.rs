@@ -0,0 +1,104 @@
+#[cfg(feature = "compression")]
+use byteorder::{ByteOrder, LittleEndian};
+#[cfg(feature = "compression")]
+use lz4_flex;
+use std::io;
+
+/// 解压后的帧默认最大 16 MiB
+#[cfg(feature = "compression")]
+const DEFAULT_MAX_FRAME_SIZE: usize = 16 << 20;
+
+/// 压缩算法，客户端在握手时提出，服务端同样启用时才会使用
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    Lz4,
+}
+
+impl Algorithm {
+    /// 当前构建是否支持该算法
+    pub(crate) fn supported(self) -> bool {
+        match self {
+            Algorithm::Lz4 => cfg!(feature = "compression"),
+        }
+    }
+
+    /// 不支持时返回 None，帧原样发送
+    pub(crate) fn compress(self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            #[cfg(feature = "compression")]
+            Algorithm::Lz4 => Some(lz4_flex::compress_prepend_size(data)),
+            #[cfg(not(feature = "compression"))]
+            Algorithm::Lz4 => {
+                let _ = data;
+                None
+            }
+        }
+    }
+
+    /// 帧声明的解压后长度超过 `max_size` 时在分配内存之前拒绝
+    pub(crate) fn decompress(self, data: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "compression")]
+            Algorithm::Lz4 => {
+                if data.len() < 4 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated lz4 frame"));
+                }
+                let size = LittleEndian::read_u32(&data[..4]) as usize;
+                if size > max_size {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("decompressed frame of {} bytes exceeds {}", size, max_size)));
+                }
+                lz4_flex::decompress_size_prepended(data)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+            }
+            #[cfg(not(feature = "compression"))]
+            Algorithm::Lz4 => {
+                let _ = (data, max_size);
+                Err(io::Error::new(io::ErrorKind::InvalidData, "lz4 compression is not enabled"))
+            }
+        }
+    }
+}
+
+/// 帧压缩策略：握手协商成功后，不小于 `threshold` 字节的帧被压缩，较小的帧原样发送。
+/// 收到的压缩帧解压后超过 `max_frame_size` 时连接被关闭
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compression {
+    algorithm: Algorithm,
+    threshold: usize,
+    max_frame_size: usize,
+}
+
+impl Compression {
+    #[cfg(feature = "compression")]
+    pub fn lz4() -> Self {
+        Self {
+            algorithm: Algorithm::Lz4,
+            threshold: 1024,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    /// 不小于该长度的帧才压缩，默认 1024 字节
+    pub fn threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// 默认 16 MiB
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub(crate) fn get_threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub(crate) fn get_max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+}
@@ -0,0 +1,255 @@
+use address::Address;
+use auth::{Headers, AUTHORIZATION};
+use serialization::Format;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 请求的剩余时间（毫秒），服务端据此计算截止时间，避免依赖双方时钟一致
+pub const TIMEOUT: &str = "timeout";
+
+thread_local! {
+    static CURRENT: RefCell<Option<RequestContext>> = const { RefCell::new(None) };
+}
+
+/// 经过校验的客户端身份，来自 mTLS 客户端证书
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    /// 证书主题，例如 `CN=path_client, O=myrpc`
+    pub subject: String,
+    /// 证书的 SubjectAltName：域名、IP、邮箱或 URI
+    pub sans: Vec<String>,
+}
+
+impl Identity {
+    pub fn new(subject: &str, sans: Vec<String>) -> Self {
+        Self {
+            subject: String::from(subject),
+            sans,
+        }
+    }
+
+    /// 通过 token 等方式认证的调用方，名称作为 CN
+    pub fn named(name: &str) -> Self {
+        Self::new(&format!("CN={}", name), vec![])
+    }
+
+    /// 主题中的 CN
+    pub fn common_name(&self) -> Option<&str> {
+        self.subject.split(',')
+            .map(|part| part.trim())
+            .find(|part| part.starts_with("CN="))
+            .map(|part| &part[3..])
+    }
+
+    /// CN 或任一 SAN 等于 `name`
+    pub fn has_name(&self, name: &str) -> bool {
+        self.common_name() == Some(name) || self.sans.iter().any(|san| san == name)
+    }
+}
+
+/// 取消标记，可以交给处理函数启动的其他线程。连接关闭时服务端取消该连接上所有请求的标记；
+/// 处理函数在服务端的 reactor 线程中同步执行，连接关闭只能在交给其他线程的工作中观察到
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// 服务端处理一次请求时的上下文
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestContext {
+    peer_addr: Address,
+    identity: Option<Identity>,
+    format: Format,
+    request_id: u32,
+    deadline: Option<Instant>,
+    metadata: Headers,
+    cancellation: CancellationToken,
+}
+
+/// 超过这个毫秒数的 `timeout` 视为没有截止时间，定时器无法表示太远的时间
+const MAX_TIMEOUT_MILLIS: u64 = 365 * 24 * 3600 * 1000;
+
+/// 请求头中 `timeout` 对应的截止时间，请求头来自对端，任意值都不能导致 panic
+pub(crate) fn deadline(headers: &Headers) -> Option<Instant> {
+    headers.get(TIMEOUT)
+        .and_then(|timeout| timeout.parse().ok())
+        .filter(|&millis| millis <= MAX_TIMEOUT_MILLIS)
+        .and_then(|millis| Instant::now().checked_add(Duration::from_millis(millis)))
+}
+
+impl RequestContext {
+    pub fn new(peer_addr: Address) -> Self {
+        Self {
+            peer_addr,
+            identity: None,
+            format: Format::Bincode,
+            request_id: 0,
+            deadline: None,
+            metadata: Headers::new(),
+            cancellation: CancellationToken::new(),
+        }
+    }
+
+    /// 客户端发来的请求 id 和请求头，请求头中的 `timeout` 决定截止时间
+    pub fn with_request(mut self, request_id: u32, metadata: Headers) -> Self {
+        self.request_id = request_id;
+        self.deadline = deadline(&metadata);
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// 调用方地址，Unix 域套接字的客户端通常没有路径
+    pub fn peer_addr(&self) -> &Address {
+        &self.peer_addr
+    }
+
+    /// 客户端证书中的身份，没有使用 mTLS 时为 None
+    pub fn identity(&self) -> Option<&Identity> {
+        self.identity.as_ref()
+    }
+
+    /// 连接使用的序列化格式，WebSocket 上的浏览器客户端为 JSON
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// 连接上的请求 id，只在同一连接内唯一
+    pub fn request_id(&self) -> u32 {
+        self.request_id
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// 距离截止时间的剩余时间，已经超时为 0
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// 客户端发送的请求头
+    pub fn metadata(&self) -> &Headers {
+        &self.metadata
+    }
+
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// 已经超过截止时间或者连接已关闭，处理函数可以提前放弃。
+    /// 处理函数自身执行期间只能观察到截止时间，连接关闭只能在交给其他线程的工作中观察到
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled() || self.remaining() == Some(Duration::from_secs(0))
+    }
+
+    /// 处理函数发出的调用继承的请求头：除凭据外的元数据和剩余时间
+    pub fn propagated_headers(&self) -> Headers {
+        let mut headers: Headers = self.metadata.iter()
+            .filter(|(name, _)| *name != AUTHORIZATION && *name != TIMEOUT)
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        if let Some(remaining) = self.remaining() {
+            headers.insert(String::from(TIMEOUT), remaining.as_millis().to_string());
+        }
+        headers
+    }
+}
+
+/// 当前线程上正在执行的处理函数的上下文，`MyRPCClient::call` 据此传递请求头和截止时间
+pub fn current() -> Option<RequestContext> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// 在 `f` 执行期间把 `context` 设为当前上下文
+pub(crate) fn enter<T, F: FnOnce() -> T>(context: &RequestContext, f: F) -> T {
+    let previous = CURRENT.with(|current| current.replace(Some(context.clone())));
+    let result = f();
+    CURRENT.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use auth::{Headers, AUTHORIZATION};
+    use context::{self, Identity, RequestContext, TIMEOUT};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn identity_test() {
+        let identity = Identity::new("CN=path_client, O=myrpc", vec![String::from("client.example.com")]);
+        assert_eq!(Some("path_client"), identity.common_name());
+        assert!(identity.has_name("path_client"));
+        assert!(identity.has_name("client.example.com"));
+        assert!(!identity.has_name("myrpc"));
+        assert_eq!(None, Identity::new("O=myrpc", vec![]).common_name());
+    }
+
+    #[test]
+    fn propagation_test() {
+        let mut headers = Headers::new();
+        headers.insert(String::from("trace"), String::from("abc"));
+        headers.insert(String::from(AUTHORIZATION), String::from("Bearer s3cr3t"));
+        headers.insert(String::from(TIMEOUT), String::from("60000"));
+        let context = RequestContext::new("memory:context".parse().unwrap()).with_request(7, headers);
+        assert_eq!(7, context.request_id());
+        assert!(context.remaining().unwrap() > Duration::from_secs(50));
+        assert!(!context.is_cancelled());
+        let propagated = context.propagated_headers();
+        assert_eq!(Some("abc"), propagated.get("trace").map(String::as_str));
+        assert!(!propagated.contains_key(AUTHORIZATION));
+        assert!(propagated[TIMEOUT].parse::<u64>().unwrap() <= 60000);
+
+        assert_eq!(None, context::current());
+        context::enter(&context, || assert_eq!(Some(7), context::current().map(|c| c.request_id())));
+        assert_eq!(None, context::current());
+
+        // 客户端发来的任意值都不会使服务端 panic
+        for timeout in &["18446744073709551615", "-1", "soon"] {
+            let mut headers = Headers::new();
+            headers.insert(String::from(TIMEOUT), String::from(*timeout));
+            let context = RequestContext::new("memory:context".parse().unwrap()).with_request(8, headers);
+            assert!(!context.is_cancelled());
+            assert_eq!(None, context.remaining());
+        }
+
+        let expired = context.clone().with_deadline(Instant::now());
+        assert!(expired.is_cancelled());
+        context.cancellation().cancel();
+        assert!(context.is_cancelled());
+    }
+}
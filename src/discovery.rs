@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use address::Address;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use serde_json;
+use toml;
+
+/// 服务发现：根据服务名解析出节点列表（TCP 地址或 Unix 域套接字路径）。
+/// 客户端会定期调用 `resolve`，节点列表变化时自动重新均衡。
+pub trait Resolver {
+    fn resolve(&mut self, service: &str) -> io::Result<Vec<Address>>;
+}
+
+/// 固定的节点列表，`MyRPCClientBuilder::new` 使用它
+pub struct StaticResolver {
+    endpoints: Vec<Address>,
+}
+
+impl StaticResolver {
+    pub fn new(endpoints: Vec<Address>) -> Self {
+        Self { endpoints }
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn resolve(&mut self, _service: &str) -> io::Result<Vec<Address>> {
+        Ok(self.endpoints.clone())
+    }
+}
+
+/// 从本地 TOML 或 JSON 文件读取节点列表，文件修改后重新加载。
+/// 文件格式为服务名到地址列表的映射，按扩展名区分格式：
+///
+/// ```toml
+/// path_server = ["127.0.0.1:6181", "127.0.0.1:6182", "unix:/run/path_server.sock"]
+/// ```
+///
+/// ```json
+/// {"path_server": ["127.0.0.1:6181", "127.0.0.1:6182"]}
+/// ```
+pub struct FileResolver {
+    path: PathBuf,
+    version: Option<(SystemTime, u64)>,
+    services: HashMap<String, Vec<Address>>,
+}
+
+impl FileResolver {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            version: None,
+            services: HashMap::new(),
+        }
+    }
+
+    fn load(&self) -> io::Result<HashMap<String, Vec<Address>>> {
+        let content = fs::read_to_string(&self.path)?;
+        let is_json = self.path.extension().is_some_and(|ext| ext == "json");
+        let services: HashMap<String, Vec<String>> = if is_json {
+            serde_json::from_str(&content).map_err(invalid_data)?
+        } else {
+            toml::from_str(&content).map_err(invalid_data)?
+        };
+        let mut result = HashMap::new();
+        for (service, addrs) in services {
+            let addrs = addrs.iter()
+                .map(|addr| addr.parse().map_err(invalid_data))
+                .collect::<io::Result<Vec<Address>>>()?;
+            result.insert(service, addrs);
+        }
+        Ok(result)
+    }
+}
+
+impl Resolver for FileResolver {
+    fn resolve(&mut self, service: &str) -> io::Result<Vec<Address>> {
+        let metadata = fs::metadata(&self.path)?;
+        let version = Some((metadata.modified()?, metadata.len()));
+        if self.version != version {
+            self.services = self.load()?;
+            self.version = version;
+        }
+        match self.services.get(service) {
+            Some(endpoints) => Ok(endpoints.clone()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("service {} not found", service))),
+        }
+    }
+}
+
+fn invalid_data<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use discovery::{Resolver, FileResolver};
+    use std::env;
+    use std::fs;
+    use std::io::ErrorKind;
+    use address::Address;
+
+    #[test]
+    fn file_resolver_test() {
+        let path = env::temp_dir().join("myrpc4rs_file_resolver_test.toml");
+        fs::write(&path, "path_server = [\"127.0.0.1:6181\", \"127.0.0.1:6182\"]\n").unwrap();
+        let mut resolver = FileResolver::new(&path);
+        let expected: Vec<Address> = vec!["127.0.0.1:6181".parse().unwrap(), "127.0.0.1:6182".parse().unwrap()];
+        assert_eq!(expected, resolver.resolve("path_server").unwrap());
+        assert_eq!(ErrorKind::NotFound, resolver.resolve("unknown").unwrap_err().kind());
+
+        fs::write(&path, "path_server = [\"127.0.0.1:6183\"]\n").unwrap();
+        let expected: Vec<Address> = vec!["127.0.0.1:6183".parse().unwrap()];
+        assert_eq!(expected, resolver.resolve("path_server").unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn json_file_resolver_test() {
+        let path = env::temp_dir().join("myrpc4rs_json_file_resolver_test.json");
+        fs::write(&path, "{\"path_server\": [\"127.0.0.1:6181\"]}").unwrap();
+        let mut resolver = FileResolver::new(&path);
+        let expected: Vec<Address> = vec!["127.0.0.1:6181".parse().unwrap()];
+        assert_eq!(expected, resolver.resolve("path_server").unwrap());
+
+        fs::write(&path, "{\"path_server\": [\"not an address\"]}").unwrap();
+        assert_eq!(ErrorKind::InvalidData, resolver.resolve("path_server").unwrap_err().kind());
+        fs::remove_file(&path).unwrap();
+    }
+}
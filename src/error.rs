@@ -1,9 +1,26 @@
-
 pub type Result<T> = ::std::result::Result<T, Error>;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum Error {
     FunctionNotFound,
     ParamDeserializeFail,
-
+    /// 连接建立失败或在等待响应时断开
+    ConnectionFail,
+    /// 所有节点都已被摘除
+    NoAvailableEndpoint,
+    /// 可用节点上该函数都已熔断
+    CircuitOpen,
+    /// 客户端无法按期望的类型反序列化返回值
+    ResultDeserializeFail,
+    /// 调用方不满足函数的访问规则
+    PermissionDenied,
+    /// 请求没有携带有效的凭据
+    Unauthenticated,
+    /// 请求在截止时间之前没有完成
+    DeadlineExceeded,
+    /// 帧的校验和不匹配，连接已关闭
+    CorruptFrame,
+    /// 超过服务端的限流或并发限制，或者客户端的调用队列已满。
+    /// `retry_after_ms` 是建议的重试等待时间，0 表示没有建议
+    ResourceExhausted { retry_after_ms: u64 },
 }
@@ -0,0 +1,254 @@
+use byteorder::{BigEndian, ByteOrder};
+use compression::{Algorithm, Compression};
+use crc32c;
+use std::error;
+use std::fmt;
+use std::io;
+
+/// 握手之后每一帧的内容以一个标志字节开头：`[长度][标志][CRC32C][内容]`，
+/// 标志的低两位是压缩算法，0 表示未压缩；启用校验时带 `CHECKSUM` 位，随后是内容（压缩后）的 CRC32C
+const COMPRESSION_MASK: u8 = 0x03;
+const LZ4: u8 = 0x01;
+const CHECKSUM: u8 = 0x04;
+/// 控制帧，内容是 `[类型][参数]`，不压缩，与其他帧一样校验
+const CONTROL: u8 = 0x08;
+
+/// 控制帧的类型
+const PING: u8 = 0x01;
+const PONG: u8 = 0x02;
+/// 流控：`[类型][请求 id][额度]`
+const WINDOW: u8 = 0x03;
+/// 取消流式调用：`[类型][请求 id]`
+const CANCEL: u8 = 0x04;
+
+/// 握手之后双方都可以发送的控制帧
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    Ping,
+    Pong,
+    /// 对端可以在流式调用 `id` 上再发送 `credit` 个值
+    Window { id: u32, credit: u32 },
+    /// 客户端不再需要流式调用 `id` 的值
+    Cancel { id: u32 },
+}
+
+/// 打开后的一帧
+#[derive(Debug, PartialEq)]
+pub enum Frame {
+    Data(Vec<u8>),
+    Control(Control),
+}
+
+impl Control {
+    /// 控制帧的内容，由 `Framing::seal_control` 加上标志字节和校验和
+    fn encode(self) -> Vec<u8> {
+        match self {
+            Control::Ping => vec![PING],
+            Control::Pong => vec![PONG],
+            Control::Window { id, credit } => {
+                let mut frame = vec![WINDOW; 9];
+                BigEndian::write_u32(&mut frame[1..5], id);
+                BigEndian::write_u32(&mut frame[5..], credit);
+                frame
+            }
+            Control::Cancel { id } => {
+                let mut frame = vec![CANCEL; 5];
+                BigEndian::write_u32(&mut frame[1..], id);
+                frame
+            }
+        }
+    }
+
+    /// 未知的控制帧返回 None
+    fn decode(package: &[u8]) -> Option<Self> {
+        match *package {
+            [PING] => Some(Control::Ping),
+            [PONG] => Some(Control::Pong),
+            [WINDOW, ..] if package.len() == 9 => Some(Control::Window {
+                id: BigEndian::read_u32(&package[1..5]),
+                credit: BigEndian::read_u32(&package[5..]),
+            }),
+            [CANCEL, ..] if package.len() == 5 => Some(Control::Cancel { id: BigEndian::read_u32(&package[1..]) }),
+            _ => None,
+        }
+    }
+}
+
+/// 帧的校验和不匹配，连接随即关闭
+#[derive(Debug)]
+pub struct CorruptFrame;
+
+impl fmt::Display for CorruptFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "frame checksum mismatch")
+    }
+}
+
+impl error::Error for CorruptFrame {}
+
+pub fn is_corrupt(error: &io::Error) -> bool {
+    error.get_ref().is_some_and(|e| e.is::<CorruptFrame>())
+}
+
+/// 握手完成后连接一端的帧格式
+pub struct Framing {
+    compression: Option<Compression>,
+    checksum: bool,
+}
+
+impl Framing {
+    /// `compression` 为 None 时不压缩，例如对端没有接受压缩
+    pub fn new(compression: Option<Compression>) -> Self {
+        Self {
+            compression,
+            checksum: false,
+        }
+    }
+
+    /// 双方发出的帧都带校验和，收到不带校验和的帧也视为损坏
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn seal(&self, payload: Vec<u8>) -> Vec<u8> {
+        if let Some(ref compression) = self.compression {
+            if payload.len() >= compression.get_threshold() {
+                let algorithm = compression.algorithm();
+                // 压缩后没有变小的帧原样发送
+                if let Some(compressed) = algorithm.compress(&payload).filter(|c| c.len() < payload.len()) {
+                    return self.with_flags(flag(algorithm), &compressed);
+                }
+            }
+        }
+        self.with_flags(0, &payload)
+    }
+
+    pub fn seal_control(&self, control: Control) -> Vec<u8> {
+        self.with_flags(CONTROL, &control.encode())
+    }
+
+    /// 去掉标志字节，校验并按需解压
+    pub fn open(&self, package: &[u8]) -> io::Result<Frame> {
+        let (flags, mut payload) = match package.split_first() {
+            Some((flags, payload)) => (*flags, payload),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "empty frame")),
+        };
+        if self.checksum || flags & CHECKSUM != 0 {
+            if flags & CHECKSUM == 0 || payload.len() < 4 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, CorruptFrame));
+            }
+            let expected = BigEndian::read_u32(&payload[..4]);
+            payload = &payload[4..];
+            if crc32c::crc32c(payload) != expected {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, CorruptFrame));
+            }
+        }
+        if flags & CONTROL != 0 {
+            return match (flags & COMPRESSION_MASK, Control::decode(payload)) {
+                (0, Some(control)) => Ok(Frame::Control(control)),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid control frame {:#x}", flags))),
+            };
+        }
+        match (flags & COMPRESSION_MASK, &self.compression) {
+            (0, _) => Ok(Frame::Data(payload.to_vec())),
+            (LZ4, Some(compression)) if compression.algorithm() == Algorithm::Lz4 => {
+                Algorithm::Lz4.decompress(payload, compression.get_max_frame_size()).map(Frame::Data)
+            }
+            // 握手没有协商压缩
+            (LZ4, _) => Err(io::Error::new(io::ErrorKind::InvalidData, "compressed frame without negotiated compression")),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown frame flags {:#x}", flags))),
+        }
+    }
+
+    fn with_flags(&self, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(payload.len() + 5);
+        if self.checksum {
+            let mut checksum = [0u8; 4];
+            BigEndian::write_u32(&mut checksum, crc32c::crc32c(payload));
+            frame.push(flags | CHECKSUM);
+            frame.extend_from_slice(&checksum);
+        } else {
+            frame.push(flags);
+        }
+        frame.extend_from_slice(payload);
+        frame
+    }
+}
+
+fn flag(algorithm: Algorithm) -> u8 {
+    match algorithm {
+        Algorithm::Lz4 => LZ4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use frame::{is_corrupt, Control, Frame, Framing};
+
+    #[test]
+    fn frame_test() {
+        let framing = Framing::new(None);
+        let payload = vec![7u8; 4096];
+        let plain = framing.seal(payload.clone());
+        assert_eq!(payload.len() + 1, plain.len());
+        assert_eq!(Frame::Data(payload), framing.open(&plain).unwrap());
+        assert!(framing.open(&[]).is_err());
+        assert!(framing.open(&[0x03, 1, 2]).is_err());
+        assert!(framing.open(&[0x01, 4, 0, 0, 0, 0x40, 1, 2, 3, 4]).is_err());
+        for control in [Control::Ping, Control::Pong, Control::Window { id: 7, credit: 16 }, Control::Cancel { id: 7 }] {
+            assert_eq!(Frame::Control(control), framing.open(&framing.seal_control(control)).unwrap());
+        }
+        // 未知的控制帧
+        assert!(framing.open(&[0x08, 0x7f]).is_err());
+    }
+
+    #[test]
+    fn checksum_test() {
+        let framing = Framing::new(None).checksum(true);
+        let payload = b"find_shortest_path".to_vec();
+        let mut sealed = framing.seal(payload.clone());
+        assert_eq!(payload.len() + 5, sealed.len());
+        assert_eq!(Frame::Data(payload.clone()), framing.open(&sealed).unwrap());
+        // 没有启用校验的一端也会校验带校验和的帧
+        assert_eq!(Frame::Data(payload.clone()), Framing::new(None).open(&sealed).unwrap());
+        sealed[8] ^= 0x10;
+        assert!(is_corrupt(&framing.open(&sealed).unwrap_err()));
+        assert!(is_corrupt(&framing.open(&Framing::new(None).seal(payload)).unwrap_err()));
+        // 心跳帧同样带校验和，损坏的帧不会被当作心跳
+        let mut ping = framing.seal_control(Control::Ping);
+        assert_eq!(6, ping.len());
+        ping[5] ^= 0x03;
+        assert!(is_corrupt(&framing.open(&ping).unwrap_err()));
+        assert!(is_corrupt(&framing.open(&Framing::new(None).seal_control(Control::Pong)).unwrap_err()));
+        // 损坏的流控和取消帧不会授予额度或者取消调用
+        for control in [Control::Window { id: 7, credit: 16 }, Control::Cancel { id: 7 }] {
+            let mut sealed = framing.seal_control(control);
+            let last = sealed.len() - 1;
+            sealed[last] ^= 0x01;
+            assert!(is_corrupt(&framing.open(&sealed).unwrap_err()));
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compression_test() {
+        use compression::Compression;
+
+        let framing = Framing::new(Some(Compression::lz4().threshold(1024)));
+        let small = vec![7u8; 100];
+        assert_eq!(0, framing.seal(small.clone())[0]);
+        let large = vec![7u8; 4096];
+        let sealed = framing.seal(large.clone());
+        assert_eq!(1, sealed[0]);
+        assert!(sealed.len() < 1024);
+        assert_eq!(Frame::Data(large), framing.open(&sealed).unwrap());
+        assert_eq!(Frame::Data(small.clone()), framing.open(&framing.seal(small)).unwrap());
+        // 没有协商压缩的一端拒绝压缩帧
+        assert!(Framing::new(None).open(&sealed).is_err());
+        // 声明的解压后长度超过上限，在分配之前拒绝
+        assert!(framing.open(&[0x01, 0xff, 0xff, 0xff, 0xff, 0]).is_err());
+        let limited = Framing::new(Some(Compression::lz4().max_frame_size(1024)));
+        assert!(limited.open(&sealed).is_err());
+    }
+}
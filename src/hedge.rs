@@ -0,0 +1,127 @@
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// 对冲请求策略：幂等函数在 `percentile` 分位延迟内没有收到响应时，向另一个节点发送同样的请求，
+/// 以先到的响应为准，取消其余请求。
+///
+/// 延迟按函数分别统计最近 `samples` 次成功调用，统计样本不足 `min_samples` 时使用 `max_delay`。
+#[derive(Debug, Clone, PartialEq)]
+pub struct HedgingPolicy {
+    percentile: f64,
+    min_delay: Duration,
+    max_delay: Duration,
+    max_hedges: u32,
+    samples: usize,
+    min_samples: usize,
+}
+
+impl HedgingPolicy {
+    /// `percentile` 取值 0 到 1，例如 0.95 表示 p95
+    pub fn new(percentile: f64) -> Self {
+        Self {
+            percentile,
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_secs(1),
+            max_hedges: 1,
+            samples: 100,
+            min_samples: 10,
+        }
+    }
+
+    pub fn delay_bounds(mut self, min_delay: Duration, max_delay: Duration) -> Self {
+        self.min_delay = min_delay;
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// 最多额外发送的请求数
+    pub fn max_hedges(mut self, max_hedges: u32) -> Self {
+        self.max_hedges = max_hedges;
+        self
+    }
+
+    pub fn samples(mut self, samples: usize, min_samples: usize) -> Self {
+        self.samples = samples;
+        self.min_samples = min_samples;
+        self
+    }
+}
+
+/// 按函数名统计最近的调用延迟
+pub(crate) struct LatencyTracker {
+    policy: HedgingPolicy,
+    latencies: HashMap<String, VecDeque<Duration>>,
+}
+
+impl LatencyTracker {
+    pub fn new(policy: HedgingPolicy) -> Self {
+        Self {
+            policy,
+            latencies: HashMap::new(),
+        }
+    }
+
+    pub fn max_hedges(&self) -> u32 {
+        self.policy.max_hedges
+    }
+
+    pub fn record(&mut self, name: &str, latency: Duration) {
+        let samples = self.policy.samples;
+        let latencies = self.latencies.entry(String::from(name)).or_default();
+        latencies.push_back(latency);
+        while latencies.len() > samples {
+            latencies.pop_front();
+        }
+    }
+
+    pub fn delay(&self, name: &str) -> Duration {
+        let latencies = match self.latencies.get(name) {
+            // `min_samples` 为 0 时也可能没有样本
+            Some(latencies) if !latencies.is_empty() && latencies.len() >= self.policy.min_samples => latencies,
+            _ => return self.policy.max_delay,
+        };
+        let mut sorted: Vec<Duration> = latencies.iter().cloned().collect();
+        sorted.sort();
+        let index = ((sorted.len() as f64 * self.policy.percentile).ceil() as usize).saturating_sub(1);
+        let delay = sorted[cmp::min(index, sorted.len() - 1)];
+        cmp::max(self.policy.min_delay, cmp::min(delay, self.policy.max_delay))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hedge::{HedgingPolicy, LatencyTracker};
+    use std::time::Duration;
+
+    #[test]
+    fn latency_tracker_test() {
+        let policy = HedgingPolicy::new(0.9)
+            .delay_bounds(Duration::from_millis(5), Duration::from_millis(500))
+            .samples(10, 5);
+        let mut tracker = LatencyTracker::new(policy);
+        assert_eq!(Duration::from_millis(500), tracker.delay("f"));
+        for ms in 1..11 {
+            tracker.record("f", Duration::from_millis(ms * 10));
+        }
+        assert_eq!(Duration::from_millis(90), tracker.delay("f"));
+        assert_eq!(Duration::from_millis(500), tracker.delay("g"));
+
+        // 只保留最近 10 个样本
+        for _ in 0..10 {
+            tracker.record("f", Duration::from_millis(1));
+        }
+        assert_eq!(Duration::from_millis(5), tracker.delay("f"));
+    }
+
+    #[test]
+    fn empty_samples_test() {
+        let policy = HedgingPolicy::new(0.9)
+            .delay_bounds(Duration::from_millis(5), Duration::from_millis(500))
+            .samples(0, 0);
+        let mut tracker = LatencyTracker::new(policy);
+        assert_eq!(Duration::from_millis(500), tracker.delay("f"));
+        tracker.record("f", Duration::from_millis(10));
+        assert_eq!(Duration::from_millis(500), tracker.delay("f"));
+    }
+}
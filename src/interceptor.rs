@@ -0,0 +1,67 @@
+use common::{Request, Response};
+use context::RequestContext;
+use error::Result;
+use server::Processes;
+use std::time::Duration;
+
+/// 服务端拦截器，包裹 `Processes::execute_function`：可以在调用 `next` 前后记录日志和指标，
+/// 修改上下文、函数名或参数后再调用 `next`，修改返回的结果，或者不调用 `next` 直接返回（例如缓存）。
+///
+/// 先添加的拦截器在外层。认证、访问策略、访问规则和限流按客户端请求的函数名在所有拦截器之前检查，
+/// 不调用 `next` 的拦截器也不能绕过；函数本身在最内层执行。
+///
+/// ```
+/// use myrpc4rs::context::RequestContext;
+/// use myrpc4rs::error::Result;
+/// use myrpc4rs::interceptor::{Interceptor, Next};
+/// use std::time::Instant;
+///
+/// struct Logging;
+///
+/// impl Interceptor for Logging {
+///     fn intercept(&self, context: &RequestContext, name: &str, params: &Vec<Vec<u8>>, next: Next) -> Result<Vec<u8>> {
+///         let started = Instant::now();
+///         let result = next.run(context, name, params);
+///         println!("{} {} {:?} {:?}", context.peer_addr(), name, result.is_ok(), started.elapsed());
+///         result
+///     }
+/// }
+/// ```
+pub trait Interceptor {
+    // 参数类型与注册的函数一致
+    #[allow(clippy::ptr_arg)]
+    fn intercept(&self, context: &RequestContext, name: &str, params: &Vec<Vec<u8>>, next: Next) -> Result<Vec<u8>>;
+}
+
+/// 拦截器链中的剩余部分：之后的拦截器和函数本身
+pub struct Next<'a> {
+    processes: &'a Processes,
+    index: usize,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(processes: &'a Processes, index: usize) -> Self {
+        Self {
+            processes,
+            index,
+        }
+    }
+
+    pub fn run(self, context: &RequestContext, name: &str, params: &Vec<Vec<u8>>) -> Result<Vec<u8>> {
+        self.processes.intercept(self.index, context, name, params)
+    }
+}
+
+/// 客户端拦截器，作用于 `MyRPCClient::call` 发出的每个调用。
+///
+/// `before` 在发送前按添加顺序调用，可以修改请求（例如添加请求头），返回 `Some` 时不再发送，
+/// 以其作为响应；`after` 在收到响应后按相反顺序调用，可以修改响应。异步调用的 `after`
+/// 在 NET 层线程中执行，因此拦截器需要是 `Send + Sync`。
+pub trait ClientInterceptor {
+    fn before(&self, _request: &mut Request) -> Option<Response> {
+        None
+    }
+
+    /// `elapsed` 为从 `before` 到收到响应的时间
+    fn after(&self, _request: &Request, _response: &mut Response, _elapsed: Duration) {}
+}
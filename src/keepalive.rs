@@ -0,0 +1,107 @@
+use futures::{task, Async, Future};
+use std::cmp;
+use std::io;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::{Handle, Timeout};
+
+/// 心跳：每隔 `interval` 向对端发送 ping，对端回复 pong；超过 `timeout` 没有收到对端的任何帧时
+/// 认为对端已经失效并关闭连接，客户端在途的调用以 `Error::ConnectionFail` 失败。
+///
+/// ```
+/// use myrpc4rs::keepalive::Heartbeat;
+/// use std::time::Duration;
+///
+/// let heartbeat = Heartbeat::new(Duration::from_secs(5)).timeout(Duration::from_secs(20));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Heartbeat {
+    interval: Duration,
+    timeout: Duration,
+}
+
+impl Heartbeat {
+    /// `timeout` 默认是三个间隔
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            timeout: interval * 3,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// 一个连接的心跳和空闲计时，由连接所在的任务 poll
+pub(crate) struct Keepalive {
+    handle: Handle,
+    heartbeat: Option<Heartbeat>,
+    idle_timeout: Option<Duration>,
+    /// 最后一次收到任何帧的时间
+    received: Instant,
+    /// 最后一次处理请求的时间，心跳不算
+    active: Instant,
+    pinged: Instant,
+    timer: Option<Timeout>,
+}
+
+impl Keepalive {
+    pub fn new(handle: Handle, heartbeat: Option<Heartbeat>, idle_timeout: Option<Duration>) -> Self {
+        let now = Instant::now();
+        Self {
+            handle,
+            heartbeat,
+            idle_timeout,
+            received: now,
+            active: now,
+            pinged: now,
+            timer: None,
+        }
+    }
+
+    pub fn received(&mut self) {
+        self.received = Instant::now();
+    }
+
+    pub fn active(&mut self) {
+        self.active = Instant::now();
+    }
+
+    /// 超时时返回错误，连接应当关闭；到了发送 ping 的时间返回 true。
+    /// `pinging` 为 false 时（例如没有握手的连接）只检查空闲时间
+    pub fn poll(&mut self, pinging: bool) -> io::Result<bool> {
+        let now = Instant::now();
+        let mut deadline = None;
+        if let Some(idle_timeout) = self.idle_timeout {
+            if now >= self.active + idle_timeout {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "idle timeout"));
+            }
+            deadline = Some(self.active + idle_timeout);
+        }
+        let mut ping = false;
+        if let (Some(heartbeat), true) = (self.heartbeat, pinging) {
+            if now >= self.received + heartbeat.timeout {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "heartbeat timeout"));
+            }
+            if now >= self.pinged + heartbeat.interval {
+                self.pinged = now;
+                ping = true;
+            }
+            let next = cmp::min(self.received + heartbeat.timeout, self.pinged + heartbeat.interval);
+            deadline = Some(deadline.map_or(next, |deadline| cmp::min(deadline, next)));
+        }
+        if let Some(deadline) = deadline {
+            match self.timer {
+                Some(ref mut timer) => timer.reset(deadline),
+                None => self.timer = Some(Timeout::new_at(deadline, &self.handle)?),
+            }
+            // poll 一次才会在到期时唤醒当前任务，已经到期则尽快再 poll
+            if let Async::Ready(()) = self.timer.as_mut().unwrap().poll()? {
+                task::current().notify();
+            }
+        }
+        Ok(ping)
+    }
+}
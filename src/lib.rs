@@ -8,15 +8,59 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate bincode;
+extern crate serde_json;
+extern crate toml;
+extern crate crc32c;
+extern crate hmac_sha256;
+extern crate hex;
+#[cfg(unix)]
+extern crate tokio_uds;
+#[cfg(feature = "tls")]
+extern crate rustls;
+#[cfg(feature = "tls")]
+extern crate rustls_pemfile;
+#[cfg(feature = "tls")]
+extern crate x509_parser;
+#[cfg(feature = "websocket")]
+extern crate tungstenite;
+#[cfg(feature = "compression")]
+extern crate lz4_flex;
+#[cfg(all(test, feature = "tls"))]
+extern crate rcgen;
 
 mod net;
 mod common;
+mod memory;
+mod frame;
+pub mod address;
 pub mod serialization;
+pub mod balance;
+pub mod discovery;
+pub mod reflection;
+pub mod retry;
+pub mod breaker;
+pub mod hedge;
+pub mod transport;
+pub mod context;
+pub mod compression;
+pub mod auth;
+pub mod acl;
+pub mod interceptor;
+pub mod limit;
+pub mod keepalive;
+pub mod stream;
+pub mod pubsub;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "websocket")]
+pub mod ws;
 
 #[macro_use]
 pub mod server;
 pub mod client;
 pub mod error;
+#[cfg(test)]
+mod testing;
 
 
 #[cfg(test)]
@@ -0,0 +1,297 @@
+use address::Address;
+use context::RequestContext;
+use error::{Error, Result};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// 每个函数正在执行的次数
+type Running = Rc<RefCell<HashMap<String, usize>>>;
+
+/// 令牌桶数量超过该值时清理已经补满的桶，避免大量不同的调用方占用内存
+const MAX_BUCKETS: usize = 4096;
+
+/// 令牌桶限流：平均每秒 `rate` 次，允许瞬时积累 `burst` 次
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    rate: f64,
+    burst: f64,
+}
+
+impl RateLimit {
+    /// `burst` 默认与每秒次数相同
+    pub fn per_second(rate: u32) -> Self {
+        Self {
+            rate: f64::from(rate),
+            burst: f64::from(rate.max(1)),
+        }
+    }
+
+    /// `burst` 默认与每分钟次数相同
+    pub fn per_minute(rate: u32) -> Self {
+        Self {
+            rate: f64::from(rate) / 60.0,
+            burst: f64::from(rate.max(1)),
+        }
+    }
+
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = f64::from(burst.max(1));
+        self
+    }
+}
+
+/// 服务端的限流和并发限制，通过 `MyRPCServer::limits` 设置，超过限制时返回
+/// `Error::ResourceExhausted`，其中带有建议的重试等待时间。反射请求不受限制。
+///
+/// ```
+/// use myrpc4rs::limit::{Limits, RateLimit};
+///
+/// let limits = Limits::new()
+///     .per_identity(RateLimit::per_second(50))
+///     .per_peer(RateLimit::per_second(100).burst(200))
+///     .function("find_shortest_path", RateLimit::per_second(20))
+///     .max_concurrent("find_shortest_path", 4);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    per_identity: Option<RateLimit>,
+    per_peer: Option<RateLimit>,
+    functions: HashMap<String, RateLimit>,
+    max_concurrent: HashMap<String, usize>,
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 每个调用方身份（CN 或主题）各自一个令牌桶，没有身份的调用方只受 `per_peer` 限制
+    pub fn per_identity(mut self, limit: RateLimit) -> Self {
+        self.per_identity = Some(limit);
+        self
+    }
+
+    /// 每个对端 IP 各自一个令牌桶，Unix 域套接字和进程内连接按对端地址区分
+    pub fn per_peer(mut self, limit: RateLimit) -> Self {
+        self.per_peer = Some(limit);
+        self
+    }
+
+    /// 函数的所有调用共享一个令牌桶
+    pub fn function(mut self, name: &str, limit: RateLimit) -> Self {
+        self.functions.insert(String::from(name), limit);
+        self
+    }
+
+    /// 同时执行的次数上限
+    pub fn max_concurrent(mut self, name: &str, max: usize) -> Self {
+        self.max_concurrent.insert(String::from(name), max);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Identity(String),
+    Peer(String),
+    Function(String),
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.updated = now;
+    }
+
+    /// 得到一个令牌还需要等待的时间
+    fn wait(&self, limit: &RateLimit) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::from_secs(0)
+        } else if limit.rate > 0.0 {
+            Duration::from_secs_f64((1.0 - self.tokens) / limit.rate)
+        } else {
+            Duration::from_secs(u64::from(u32::MAX))
+        }
+    }
+}
+
+/// 按 `Limits` 检查每个请求，在服务端的 reactor 线程中使用
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    limits: Limits,
+    buckets: HashMap<Key, TokenBucket>,
+    running: Running,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            buckets: HashMap::new(),
+            running: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    /// 所有适用的令牌桶都有令牌时才各取一个；返回的 `Permit` 在函数执行完之前保留并发名额
+    pub fn acquire(&mut self, context: &RequestContext, name: &str) -> Result<Permit> {
+        let max = self.limits.max_concurrent.get(name).cloned();
+        if let Some(max) = max {
+            if self.running.borrow().get(name).is_some_and(|count| *count >= max) {
+                return Err(Error::ResourceExhausted { retry_after_ms: 0 });
+            }
+        }
+        self.take(context, name, Instant::now())?;
+        if max.is_none() {
+            return Ok(Permit(None));
+        }
+        *self.running.borrow_mut().entry(String::from(name)).or_insert(0) += 1;
+        Ok(Permit(Some((Rc::clone(&self.running), String::from(name)))))
+    }
+
+    fn take(&mut self, context: &RequestContext, name: &str, now: Instant) -> Result<()> {
+        let mut keys = Vec::new();
+        if let Some(limit) = self.limits.functions.get(name) {
+            keys.push((Key::Function(String::from(name)), *limit));
+        }
+        if let Some(limit) = self.limits.per_identity {
+            if let Some(identity) = context.identity() {
+                let name = identity.common_name().unwrap_or(&identity.subject);
+                keys.push((Key::Identity(String::from(name)), limit));
+            }
+        }
+        if let Some(limit) = self.limits.per_peer {
+            keys.push((Key::Peer(peer(context.peer_addr())), limit));
+        }
+        if keys.is_empty() {
+            return Ok(());
+        }
+        if self.buckets.len() >= MAX_BUCKETS {
+            self.prune(now);
+        }
+        let mut wait = Duration::from_secs(0);
+        for (key, limit) in &keys {
+            let bucket = self.buckets.entry(key.clone()).or_insert_with(|| TokenBucket::new(limit, now));
+            bucket.refill(limit, now);
+            wait = wait.max(bucket.wait(limit));
+        }
+        if wait > Duration::from_secs(0) {
+            // 向上取整，避免客户端按提示等待后仍然差一点令牌
+            let retry_after_ms = (wait.as_micros() as u64).div_ceil(1000);
+            return Err(Error::ResourceExhausted { retry_after_ms });
+        }
+        for (key, _) in keys {
+            if let Some(bucket) = self.buckets.get_mut(&key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// 去掉已经补满的桶，它们和新建的桶没有区别
+    fn prune(&mut self, now: Instant) {
+        let limits = &self.limits;
+        self.buckets.retain(|key, bucket| {
+            let limit = match *key {
+                Key::Identity(_) => limits.per_identity,
+                Key::Peer(_) => limits.per_peer,
+                Key::Function(ref name) => limits.functions.get(name).cloned(),
+            };
+            match limit {
+                Some(limit) => {
+                    bucket.refill(&limit, now);
+                    bucket.tokens < limit.burst
+                }
+                None => false,
+            }
+        });
+    }
+}
+
+/// 对端 IP，TCP 连接不区分端口
+fn peer(address: &Address) -> String {
+    match *address {
+        Address::Tcp(ref addr) => addr.ip().to_string(),
+        ref address => address.to_string(),
+    }
+}
+
+/// 函数的并发名额，drop 时归还
+pub(crate) struct Permit(Option<(Running, String)>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some((ref running, ref name)) = self.0 {
+            if let Some(count) = running.borrow_mut().get_mut(name) {
+                *count -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use context::{Identity, RequestContext};
+    use error::Error;
+    use limit::{Limiter, Limits, RateLimit};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn token_bucket_test() {
+        let mut limiter = Limiter::new(Limits::new().per_peer(RateLimit::per_second(10).burst(2)));
+        let context = RequestContext::new("10.0.0.1:5000".parse().unwrap());
+        let now = Instant::now();
+        assert_eq!(Ok(()), limiter.take(&context, "add", now));
+        // 同一 IP 的其他连接共享令牌桶
+        let other_port = RequestContext::new("10.0.0.1:5001".parse().unwrap());
+        assert_eq!(Ok(()), limiter.take(&other_port, "add", now));
+        assert_eq!(Err(Error::ResourceExhausted { retry_after_ms: 100 }), limiter.take(&context, "add", now));
+        assert_eq!(Ok(()), limiter.take(&context, "add", now + Duration::from_millis(100)));
+        let other_peer = RequestContext::new("10.0.0.2:5000".parse().unwrap());
+        assert_eq!(Ok(()), limiter.take(&other_peer, "add", now));
+    }
+
+    #[test]
+    fn identity_limit_test() {
+        let limits = Limits::new()
+            .per_identity(RateLimit::per_minute(1))
+            .function("find_shortest_path", RateLimit::per_second(1).burst(3));
+        let mut limiter = Limiter::new(limits);
+        let context = RequestContext::new("10.0.0.1:5000".parse().unwrap());
+        let alice = context.clone().with_identity(Identity::named("alice"));
+        let bob = context.clone().with_identity(Identity::named("bob"));
+        let now = Instant::now();
+        assert_eq!(Ok(()), limiter.take(&alice, "find_shortest_path", now));
+        assert_eq!(Err(Error::ResourceExhausted { retry_after_ms: 60000 }), limiter.take(&alice, "find_shortest_path", now));
+        // 被拒绝的请求不消耗函数的令牌
+        assert_eq!(Ok(()), limiter.take(&bob, "find_shortest_path", now));
+        assert_eq!(Ok(()), limiter.take(&context, "find_shortest_path", now));
+        assert_eq!(Err(Error::ResourceExhausted { retry_after_ms: 1000 }), limiter.take(&context, "find_shortest_path", now));
+    }
+
+    #[test]
+    fn concurrency_test() {
+        let mut limiter = Limiter::new(Limits::new().max_concurrent("find_shortest_path", 1));
+        let context = RequestContext::new("10.0.0.1:5000".parse().unwrap());
+        let permit = limiter.acquire(&context, "find_shortest_path").unwrap();
+        assert!(limiter.acquire(&context, "find_shortest_path").is_err());
+        assert!(limiter.acquire(&context, "add").is_ok());
+        drop(permit);
+        assert!(limiter.acquire(&context, "find_shortest_path").is_ok());
+    }
+}
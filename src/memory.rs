@@ -0,0 +1,143 @@
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::task::{self, Task};
+use futures::{Async, Poll};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// 单向的内存管道
+struct Pipe {
+    buffer: Vec<u8>,
+    closed: bool,
+    reader: Option<Task>,
+}
+
+impl Pipe {
+    fn new() -> Arc<Mutex<Pipe>> {
+        Arc::new(Mutex::new(Pipe {
+            buffer: Vec::new(),
+            closed: false,
+            reader: None,
+        }))
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        if let Some(reader) = self.reader.take() {
+            reader.notify();
+        }
+    }
+}
+
+/// 进程内连接的一端，可以在不同线程的 reactor 中使用
+pub struct MemoryStream {
+    read: Arc<Mutex<Pipe>>,
+    write: Arc<Mutex<Pipe>>,
+}
+
+impl MemoryStream {
+    pub fn pair() -> (MemoryStream, MemoryStream) {
+        let (a, b) = (Pipe::new(), Pipe::new());
+        (MemoryStream { read: Arc::clone(&a), write: Arc::clone(&b) },
+         MemoryStream { read: b, write: a })
+    }
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = self.read.lock().unwrap();
+        if pipe.buffer.is_empty() {
+            if pipe.closed {
+                return Ok(0);
+            }
+            pipe.reader = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = ::std::cmp::min(buf.len(), pipe.buffer.len());
+        buf[..n].copy_from_slice(&pipe.buffer[..n]);
+        pipe.buffer.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.write.lock().unwrap();
+        if pipe.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        pipe.buffer.extend_from_slice(buf);
+        if let Some(reader) = pipe.reader.take() {
+            reader.notify();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for MemoryStream {}
+
+impl AsyncWrite for MemoryStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.write.lock().unwrap().close();
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        self.write.lock().unwrap().close();
+        self.read.lock().unwrap().close();
+    }
+}
+
+struct Listener {
+    sender: UnboundedSender<MemoryStream>,
+    /// 开始监听前为 Some，此前到达的连接在通道中排队
+    receiver: Option<UnboundedReceiver<MemoryStream>>,
+}
+
+type Registry = Mutex<HashMap<String, Listener>>;
+
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// 以 `name` 注册进程内服务端，此后的连接排队等待 `listen`。同名的旧服务端会被替换
+pub fn bind(name: &str) {
+    let (sender, receiver) = mpsc::unbounded();
+    registry().lock().unwrap().insert(String::from(name), Listener { sender, receiver: Some(receiver) });
+}
+
+/// 返回新连接的接收端，尚未 `bind` 时先注册
+pub fn listen(name: &str) -> UnboundedReceiver<MemoryStream> {
+    let mut registry = registry().lock().unwrap();
+    if let Some(receiver) = registry.get_mut(name).and_then(|listener| listener.receiver.take()) {
+        return receiver;
+    }
+    let (sender, receiver) = mpsc::unbounded();
+    registry.insert(String::from(name), Listener { sender, receiver: None });
+    receiver
+}
+
+/// 服务端停止后注销
+pub fn unlisten(name: &str) {
+    registry().lock().unwrap().remove(name);
+}
+
+pub fn connect(name: &str) -> io::Result<MemoryStream> {
+    let registry = registry().lock().unwrap();
+    let sender = match registry.get(name) {
+        Some(listener) => &listener.sender,
+        None => return Err(io::Error::new(io::ErrorKind::ConnectionRefused, format!("memory:{} not found", name))),
+    };
+    let (client, server) = MemoryStream::pair();
+    sender.unbounded_send(server)
+        .map_err(|_| io::Error::new(io::ErrorKind::ConnectionRefused, format!("memory:{} stopped", name)))?;
+    Ok(client)
+}
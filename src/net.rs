@@ -1,9 +1,8 @@
 use futures::{Future, Stream};
-use tokio_core::net::TcpListener;
-use tokio_core::reactor::Core;
-use std::net::SocketAddr;
+use futures::stream as futures_stream;
+use tokio_core::reactor::{Core, Handle, Timeout, Interval};
+use address::Address;
 use futures::Async;
-use tokio_core::net::TcpStream;
 use bytes::BytesMut;
 use std::io;
 use std::io::Cursor;
@@ -12,153 +11,403 @@ use futures::future;
 use std::cell::RefCell;
 use server::Processes;
 use std::rc::Rc;
-use common::Request;
+use common::{Handshake, Request, HANDSHAKE, HANDSHAKE_ID};
 use serialization::Serializer;
-use common::Response;
+use common::{Response, StreamFrame};
 use error::Error;
 use futures::Poll;
+use futures::task;
 use bytes::BufMut;
 use tokio_io::AsyncRead;
 use tokio_io::AsyncWrite;
 use bytes::IntoBuf;
-use serialization::BincodeSerializer;
-use std::sync::mpsc::Receiver;
-use std::sync::Arc;
-use std::collections::HashMap;
+use serialization::Format;
+use futures::sync::mpsc::{self, Receiver, UnboundedReceiver, UnboundedSender};
+use memory;
+use server::ShutdownHandle;
+use std::collections::{HashMap, HashSet};
 use byteorder::WriteBytesExt;
-use std::u32;
+use balance::{Balancer, EndpointView, Ejection};
+use discovery::Resolver;
+use client::MyRPCClientBuilder;
+use retry::RetryPolicy;
+use breaker::Circuits;
+use hedge::LatencyTracker;
+use std::time::Instant;
+use reflection::{self, FunctionInfo};
+use context::{self, RequestContext};
+use transport::{Connecting, DefaultTransport, Socket, Transport};
+use compression::Compression;
+use frame::{self, Control, Frame, Framing};
+use keepalive::{Heartbeat, Keepalive};
+use std::cell::Cell;
+use std::time::Duration;
+use auth::{CredentialProvider, Headers};
+use stream::{Incoming, ResponseStream, Window, DEFAULT_WINDOW};
+use std::sync::Arc;
+#[cfg(feature = "tls")]
+use std::mem;
+
+pub type Callback = Box<dyn FnMut(&Format, &Response) + Send>;
 
+/// 发送缓冲区的默认上限，超过后服务端暂停读取请求，客户端暂停从队列中取出调用
+pub(crate) const DEFAULT_WRITE_BUFFER_LIMIT: usize = 1 << 20;
+/// 收到的帧（长度头之后的部分）的默认上限，对端声明更长的帧时关闭连接
+pub(crate) const DEFAULT_MAX_FRAME_SIZE: usize = 16 << 20;
+
+/// 客户端线程与 NET 层线程之间传递的消息
+pub enum Command {
+    /// 请求、请求键的哈希值（用于一致性哈希）、回调
+    Call(Request, Option<u64>, Callback),
+    /// 双向流式调用，另外带有客户端发送消息的额度
+    Open(Request, Option<u64>, Callback, Arc<Window>),
+    /// 双向流式调用中客户端发送的一个消息
+    Message(Request),
+    /// 客户端取走了流式响应中的值，归还服务端的额度
+    Window(u32, u32),
+    /// 客户端不再需要流式响应
+    Cancel(u32),
+    Stop,
+}
 
 pub struct Server {
-    socket_addr: SocketAddr,
+    /// 监听地址及其传输层，第一个是创建时指定的地址
+    listeners: Vec<(Address, Box<dyn Transport>)>,
+    compression: Option<Compression>,
+    max_connections: Option<usize>,
+    idle_timeout: Option<Duration>,
+    heartbeat: Option<Heartbeat>,
+    write_buffer_limit: usize,
+    max_frame_size: usize,
+    shutdown_sender: UnboundedSender<()>,
+    shutdown_receiver: Option<UnboundedReceiver<()>>,
 }
 
 impl Server {
-    pub fn new(socket_addr: SocketAddr) -> Self {
-        Self {
-            socket_addr,
+    pub fn new(address: Address) -> Self {
+        let (shutdown_sender, shutdown_receiver) = mpsc::unbounded();
+        let mut server = Self {
+            listeners: Vec::new(),
+            compression: None,
+            max_connections: None,
+            idle_timeout: None,
+            heartbeat: None,
+            write_buffer_limit: DEFAULT_WRITE_BUFFER_LIMIT,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            shutdown_sender,
+            shutdown_receiver: Some(shutdown_receiver),
+        };
+        server.add_listener(address, Box::new(DefaultTransport));
+        server
+    }
+
+    pub fn add_listener(&mut self, address: Address, transport: Box<dyn Transport>) {
+        // 进程内服务端在创建时注册，之后的连接排队等待 `start`
+        if let Address::Memory(ref name) = address {
+            memory::bind(name);
         }
+        self.listeners.push((address, transport));
+    }
+
+    pub fn set_transport(&mut self, transport: Box<dyn Transport>) {
+        self.listeners[0].1 = transport;
+    }
+
+    /// 取出当前传输层，用于在其上包装 TLS
+    #[cfg(feature = "tls")]
+    pub fn take_transport(&mut self) -> Box<dyn Transport> {
+        mem::replace(&mut self.listeners[0].1, Box::new(DefaultTransport))
+    }
+
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = Some(compression);
+    }
+
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.max_connections = Some(max_connections);
+    }
+
+    pub fn set_idle_timeout(&mut self, idle_timeout: Duration) {
+        self.idle_timeout = Some(idle_timeout);
+    }
+
+    pub fn set_heartbeat(&mut self, heartbeat: Heartbeat) {
+        self.heartbeat = Some(heartbeat);
+    }
+
+    pub fn set_write_buffer_limit(&mut self, write_buffer_limit: usize) {
+        self.write_buffer_limit = write_buffer_limit;
+    }
+
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
     }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::new(self.shutdown_sender.clone())
+    }
+
+    /// 运行直到通过 `ShutdownHandle` 停止
     pub fn start(&mut self, processes: Rc<Processes>) {
         let mut core = Core::new().unwrap();
         let handle = core.handle();
-        let listener = TcpListener::bind(&self.socket_addr, &handle).unwrap();
-//        let process_cell = RefCell::new(process);
-        let server = listener.incoming().for_each(|(socket, _)| {
-            let packages = ServerPackages::new(socket, Rc::clone(&processes));
-//            let pro = process_cell.borrow_mut();
-            let package_handler = packages.into_future()
-                .then(|_| {
-                    future::ok(())
-                });
-            handle.spawn(package_handler);
-            Ok(())
-        });
-        core.run(server).unwrap();
+        let shutdown = match self.shutdown_receiver.take() {
+            Some(receiver) => receiver,
+            None => return,
+        };
+        // 所有监听地址上的连接数
+        let connections = Rc::new(Cell::new(0));
+        let (processes, handle_ref, compression, connections) = (&processes, &handle, &self.compression, &connections);
+        let (max_connections, idle_timeout, heartbeat) = (self.max_connections, self.idle_timeout, self.heartbeat);
+        let (write_buffer_limit, max_frame_size) = (self.write_buffer_limit, self.max_frame_size);
+        let servers = self.listeners.iter().map(|(address, transport)| {
+            transport.listen(address, handle_ref).unwrap().for_each(move |(socket, addr)| {
+                if max_connections.is_some_and(|max| connections.get() >= max) {
+                    println!("ERR:连接数已达上限，拒绝 {}", addr);
+                    return Ok(());
+                }
+                let keepalive = Keepalive::new(handle_ref.clone(), heartbeat, idle_timeout);
+                let mut packages = ServerPackages::new(socket, addr, Rc::clone(processes), compression.clone(), keepalive, Rc::clone(connections));
+                packages.write_buffer_limit = write_buffer_limit;
+                packages.max_frame_size = max_frame_size;
+                let package_handler = packages.into_future()
+                    .then(|_| {
+                        future::ok(())
+                    });
+                handle_ref.spawn(package_handler);
+                Ok(())
+            })
+        }).collect::<Vec<_>>();
+        let server = future::select_all(servers).map(|_| ()).map_err(|(e, _, _)| e);
+        let shutdown = shutdown.into_future()
+            .map(|_| ())
+            .map_err(|_| io::Error::other("shutdown channel closed"));
+        core.run(server.select(shutdown).map(|_| ()).map_err(|(e, _)| e)).unwrap();
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        for (address, _) in &self.listeners {
+            if let Address::Memory(ref name) = *address {
+                memory::unlisten(name);
+            }
+        }
     }
 }
 
 pub struct Client {
-    socket_addr: SocketAddr,
-    serializer: Rc<BincodeSerializer>,
+    builder: MyRPCClientBuilder,
 }
 
 impl Client {
-    pub fn new(socket_addr: SocketAddr, serializer: Rc<BincodeSerializer>) -> Self {
+    pub fn new(builder: MyRPCClientBuilder) -> Self {
         Self {
-            socket_addr,
-            serializer,
+            builder,
         }
     }
-    pub fn start(&self, receiver: &Arc<Receiver<(Request, Box<FnMut(&Rc<BincodeSerializer>, &Response) + Send>)>>) {
+    pub fn start(self, receiver: Receiver<Command>) {
         let mut core = Core::new().unwrap();
-        let handle = core.handle();
-        let socket = TcpStream::connect(&self.socket_addr, &handle).and_then(|socket| {
-            let mut packages = ClientPackages::new(socket, Arc::clone(receiver), Rc::clone(&self.serializer));
-            loop {
-                match packages.poll() {
-                    Ok(result) => match result {
-                        Async::NotReady => continue,
-                        Async::Ready(_) => break,
-                    },
-                    Err(e) => { println!("ERR:{:?}", e) }
-                }
-                break;
-            }
-            Ok(())
-        });
-        core.run(socket).unwrap();
+        let dispatcher = ClientDispatcher::new(core.handle(), self, receiver);
+        core.run(dispatcher).unwrap();
+    }
+}
+
+/// 从缓冲区中切出一个完整的包（去掉长度头），不足一个包时返回 None，声明的长度超过 `max_frame_size` 时返回错误
+fn split_package(buffer: &mut BytesMut, max_frame_size: usize) -> io::Result<Option<BytesMut>> {
+    if buffer.len() < 4 {
+        return Ok(None);
+    }
+    let mut rdr = Cursor::new(&buffer[..4]);
+    let package_length = rdr.read_u32::<BigEndian>().unwrap() as usize;
+    if package_length > max_frame_size {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes exceeds {}", package_length, max_frame_size)));
+    }
+    if buffer.len() < 4 + package_length {
+        return Ok(None);
+    }
+    let mut package = buffer.split_to(4 + package_length);
+    Ok(Some(package.split_off(4)))
+}
+
+fn write_package(buffer: &RefCell<BytesMut>, package: &[u8]) {
+    let mut length = vec![];
+    length.write_u32::<BigEndian>(package.len() as u32).unwrap();
+    let mut write_buffer = buffer.borrow_mut();
+    write_buffer.reserve(length.len() + package.len());
+    write_buffer.put(&length[..]);
+    write_buffer.put(package);
+}
+
+/// 读到连接暂时没有数据为止；缓冲区超过 `limit` 时暂停读取，先处理已经收到的包
+fn fill_read_buf<S: AsyncRead>(socket: &mut S, read_buffer: &mut BytesMut, limit: usize) -> Poll<(), io::Error> {
+    while read_buffer.len() < limit {
+        read_buffer.reserve(1024);
+        let n = try_ready!(AsyncRead::read_buf(socket, read_buffer));
+        if n == 0 {
+            return Ok(Async::Ready(()));
+        }
+    }
+    // 连接中可能还有数据，不会再收到可读通知
+    task::current().notify();
+    Ok(Async::NotReady)
+}
+
+fn poll_flush<S: AsyncWrite>(socket: &mut S, write_buffer: &RefCell<BytesMut>) -> Poll<(), io::Error> {
+    let mut write_buffer = write_buffer.borrow_mut();
+    while !write_buffer.is_empty() {
+        let n = try_ready!(socket.write_buf(&mut (&*write_buffer).into_buf()));
+        assert!(n > 0);
+        let _ = write_buffer.split_to(n);
     }
+    socket.poll_flush()
 }
 
 pub struct ServerPackages {
-    socket: TcpStream,
+    socket: Box<dyn Socket>,
+    /// 收到第一个请求时（握手已经完成）创建
+    context: Option<RequestContext>,
+    peer_addr: Address,
     read_buffer: BytesMut,
     write_buffer: RefCell<BytesMut>,
     processes: Rc<Processes>,
+    compression: Option<Compression>,
+    /// 握手完成后的帧格式，没有握手的连接（例如浏览器）不带标志字节，也不发送心跳
+    framing: Option<Framing>,
+    keepalive: Keepalive,
+    connections: Rc<Cell<usize>>,
+    write_buffer_limit: usize,
+    max_frame_size: usize,
+    /// 还没有结束的流式响应，按开始的顺序
+    streams: Vec<ServerStream>,
+    /// 双向流式调用中还会收到客户端消息的调用
+    incoming: Vec<Incoming>,
+}
+
+/// 流式函数的一个响应流，帧以请求的 id 区分
+struct ServerStream {
+    id: u32,
+    name: String,
+    sequence: u32,
+    stream: ResponseStream,
+    /// 还可以发送的值的个数，没有握手的连接不使用流控
+    credit: Option<u32>,
+}
+
+impl ServerStream {
+    fn frame(&mut self, result: Result<Vec<u8>, Error>, end: bool) -> Response {
+        let frame = StreamFrame { sequence: self.sequence, end };
+        self.sequence += 1;
+        if let (Some(credit), false) = (self.credit.as_mut(), end) {
+            *credit -= 1;
+        }
+        Response {
+            id: self.id,
+            name: self.name.clone(),
+            result,
+            stream: Some(frame),
+        }
+    }
 }
 
 impl Stream for ServerPackages {
     type Item = BytesMut;
     type Error = io::Error;
 
-    fn poll(&mut self) -> Result<Async<Option<<Self as Stream>::Item>>, <Self as Stream>::Error> {
-//        let _ = self.poll_flush()?;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         loop {
-            let sock_closed = self.fill_read_buf()?.is_ready();
-            if self.read_buffer.len() < 4 {
-                if sock_closed {
-                    return Ok(Async::Ready(None));
-                } else {
-                    return Ok(Async::NotReady);
+            // 客户端读得太慢，响应积压到上限后不再读取和处理请求，等发送缓冲区可写时被唤醒
+            if self.write_buffer.borrow().len() >= self.write_buffer_limit {
+                let _ = poll_flush(&mut self.socket, &self.write_buffer)?;
+                if self.write_buffer.borrow().len() >= self.write_buffer_limit {
+                    break;
                 }
             }
-            let mut package_length = [0; 4];
-            for i in 0..4 {
-                package_length[i] = self.read_buffer[i];
-            }
-            let mut rdr = Cursor::new(package_length);
-            let package_length = rdr.read_u32::<BigEndian>().unwrap() as usize;
-            if self.read_buffer.len() < 4 + package_length {
-                return Ok(Async::NotReady);
-            }
-            let mut package = self.read_buffer.split_to(4 + package_length);
-            let package = package.split_off(4);
-            {
-                let response = self.process(&package);
-                let serializer = self.get_processes().get_serializer();
-                let result = match serializer.serialize(&response) {
-                    Ok(bytes) => {
-                        bytes
+            let sock_closed = fill_read_buf(&mut self.socket, &mut self.read_buffer, 4 + self.max_frame_size)?.is_ready();
+            let package = split_package(&mut self.read_buffer, self.max_frame_size).inspect_err(|e| {
+                println!("ERR:连接 {} 收到过长的帧，关闭连接: {}", self.peer_addr, e);
+            })?;
+            let package = match package {
+                Some(package) => package,
+                None if sock_closed => return Ok(Async::Ready(None)),
+                None => break,
+            };
+            self.keepalive.received();
+            let result = match self.framing {
+                Some(ref framing) => {
+                    let frame = framing.open(&package).inspect_err(|e| {
+                        println!("ERR:连接 {} 收到无效的帧，关闭连接: {}", self.peer_addr, e);
+                    })?;
+                    match frame {
+                        Frame::Data(package) => self.process(&package).map(|result| self.framing.as_ref().unwrap().seal(result)),
+                        Frame::Control(Control::Ping) => {
+                            write_package(&self.write_buffer, &framing.seal_control(Control::Pong));
+                            continue;
+                        }
+                        Frame::Control(Control::Window { id, credit }) => {
+                            if let Some(stream) = self.streams.iter_mut().find(|stream| stream.id == id) {
+                                stream.credit = stream.credit.map(|current| current.saturating_add(credit));
+                            }
+                            continue;
+                        }
+                        Frame::Control(Control::Cancel { id }) => {
+                            self.finish_stream(id);
+                            continue;
+                        }
+                        Frame::Control(Control::Pong) => continue,
                     }
-                    Err(_) => { /*不应该发生*/panic!("should not happend") }
-                };
-                let mut length = vec![];
-                length.write_u32::<BigEndian>(result.len() as u32).unwrap();
-                self.write(&length);
-                self.write(&result);
+                }
+                None => self.process(&package),
+            };
+            self.keepalive.active();
+            if let Some(result) = result {
+                write_package(&self.write_buffer, &result);
+                let _ = poll_flush(&mut self.socket, &self.write_buffer)?;
             }
-            let _ = self.poll_flush()?;
         }
+        self.poll_incoming();
+        self.poll_streams();
+        // 有进行中的流式响应（例如订阅）的连接不算空闲
+        if !self.streams.is_empty() {
+            self.keepalive.active();
+        }
+        // 在读完已到达的请求之后检查，刚到达的请求不会因空闲超时被丢弃
+        match self.keepalive.poll(self.framing.is_some()) {
+            Ok(true) => {
+                if let Some(ref framing) = self.framing {
+                    write_package(&self.write_buffer, &framing.seal_control(Control::Ping));
+                }
+            }
+            Ok(false) => {}
+            Err(e) => {
+                println!("ERR:连接 {} {}，关闭连接", self.peer_addr, e);
+                return Ok(Async::Ready(None));
+            }
+        }
+        let _ = poll_flush(&mut self.socket, &self.write_buffer)?;
+        Ok(Async::NotReady)
     }
 }
 
 impl ServerPackages {
-    fn new(socket: TcpStream, processes: Rc<Processes>) -> Self {
+    fn new(socket: Box<dyn Socket>, peer_addr: Address, processes: Rc<Processes>, compression: Option<Compression>,
+           keepalive: Keepalive, connections: Rc<Cell<usize>>) -> Self {
+        connections.set(connections.get() + 1);
         Self {
             socket,
+            context: None,
+            peer_addr,
             read_buffer: BytesMut::new(),
             write_buffer: RefCell::new(BytesMut::new()),
             processes,
-        }
-    }
-    fn fill_read_buf(&mut self) -> Result<Async<()>, io::Error> {
-        loop {
-            self.read_buffer.reserve(1024);
-            let n = try_ready!(self.socket.read_buf(&mut self.read_buffer));
-            if n == 0 {
-                return Ok(Async::Ready(()));
-            }
+            compression,
+            framing: None,
+            keepalive,
+            connections,
+            write_buffer_limit: DEFAULT_WRITE_BUFFER_LIMIT,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            streams: Vec::new(),
+            incoming: Vec::new(),
         }
     }
 
@@ -166,168 +415,982 @@ impl ServerPackages {
         &self.processes
     }
 
-    fn write(&self, line: &[u8]) {
-        let mut write_buffer = self.write_buffer.borrow_mut();
-        write_buffer.reserve(line.len());
-        write_buffer.put(line);
-    }
-
-    fn poll_flush(&mut self) -> Poll<(), io::Error> {
-        let mut write_buffer = self.write_buffer.borrow_mut();
-        while !write_buffer.is_empty() {
-            let n = try_ready!(self.socket.write_buf(&mut (&*write_buffer).into_buf()));
-            let _ = write_buffer.split_to(n);
+    fn context(&mut self) -> &RequestContext {
+        if self.context.is_none() {
+            let mut context = RequestContext::new(self.peer_addr.clone()).with_format(self.socket.format());
+            if let Some(identity) = self.socket.peer_identity() {
+                context = context.with_identity(identity);
+            }
+            self.context = Some(context);
         }
-        Ok(Async::Ready(()))
+        self.context.as_ref().unwrap()
     }
 
-    fn process(&self, package: &BytesMut) -> Response {
-        let processes = self.get_processes();
-        let serializer = processes.get_serializer();
-        let request: Request = match serializer.deserialize(&package[..]) {
+    /// 处理一个请求，返回按连接格式编码的响应；单向调用没有响应
+    fn process(&mut self, package: &[u8]) -> Option<Vec<u8>> {
+        let first = self.context.is_none();
+        let format = self.context().format();
+        let request = match Request::decode(format, package) {
             Ok(t) => t,
-            Err(_) => { return Response::err_unknow_request(Error::ParamDeserializeFail); }
+            Err(_) => return Some(encode(format, Response::err_unknow_request(Error::ParamDeserializeFail))),
         };
-        match processes.execute_function(&request.name, &request.params) {
+        // 只有连接上的第一个请求可以是握手，之后帧格式不能再改变
+        if request.name == HANDSHAKE && first {
+            return Some(self.handshake(format, request));
+        }
+        if request.name == HANDSHAKE {
+            return Some(encode(format, Response::err(request, Error::FunctionNotFound)));
+        }
+        if request.stream.is_some_and(|frame| frame.sequence > 0) {
+            let id = request.id;
+            let exceeded = match self.incoming.iter_mut().find(|incoming| incoming.id == id) {
+                Some(incoming) => !incoming.receive(request),
+                None => false,
+            };
+            // 客户端没有遵守授予的额度，结束调用而不是无限制地缓存消息；没有握手的连接不使用流控
+            if exceeded && self.framing.is_some() {
+                println!("ERR:连接 {} 的流式调用 {} 超出发送额度，结束调用", self.peer_addr, id);
+                let error = Err(Error::ResourceExhausted { retry_after_ms: 0 });
+                let response = self.streams.iter_mut().find(|stream| stream.id == id).map(|stream| stream.frame(error, true));
+                self.finish_stream(id);
+                return response.map(|response| encode(format, response));
+            }
+            return None;
+        }
+        let one_way = request.one_way;
+        // 流式函数的值随后由 `poll_streams` 写出
+        let response = self.execute(request)?;
+        // 调用已经结束，之后收到的消息被丢弃
+        self.incoming.retain(|incoming| incoming.id != response.id);
+        if one_way {
+            if let Err(ref err) = response.result {
+                println!("ERR:单向调用 {} 失败: {:?}", response.name, err);
+            }
+            return None;
+        }
+        Some(encode(format, response))
+    }
+
+    /// 开始了流式响应时返回 None
+    fn execute(&mut self, request: Request) -> Option<Response> {
+        let mut context = self.context.as_ref().unwrap().clone().with_request(request.id, request.headers.clone());
+        match self.processes.authenticate(&context, &request.name, &request.headers) {
+            Ok(Some(identity)) => context = context.with_identity(identity),
+            Ok(None) => {}
+            Err(err) => return Some(Response::err(request, err)),
+        }
+        if self.processes.is_stream_function(&request.name) {
+            // 客户端随后还会发送消息时，为其准备接收的一端；流式函数丢弃消息流时消息被直接丢弃
+            let messages = match request.stream {
+                Some(frame) if !frame.end => {
+                    let (incoming, messages) = Incoming::new(request.id);
+                    self.incoming.push(incoming);
+                    messages
+                }
+                _ => Box::new(futures_stream::empty()),
+            };
+            let stream = match self.processes.execute_stream(&context, &request.name, &request.params, messages) {
+                Ok(stream) => stream,
+                Err(err) => return Some(Response::err(request, err)),
+            };
+            // 单向调用不需要流中的值，丢弃流使产出值的一方停止
+            if !request.one_way {
+                let credit = self.framing.as_ref().map(|_| DEFAULT_WINDOW);
+                self.streams.push(ServerStream { id: request.id, name: request.name, sequence: 0, stream, credit });
+            }
+            return None;
+        }
+        Some(match self.get_processes().execute_function(&context, &request.name, &request.params) {
             Ok(result) => Response::from(request, result),
             Err(err) => Response::err(request, err),
+        })
+    }
+
+    /// 把流式响应产出的值写入发送缓冲区，缓冲区达到上限时暂停，值留在流中
+    fn poll_streams(&mut self) {
+        if self.streams.is_empty() {
+            return;
+        }
+        let format = self.context().format();
+        let mut i = 0;
+        while i < self.streams.len() && self.write_buffer.borrow().len() < self.write_buffer_limit {
+            // 客户端还没有取走已经发出的值
+            if self.streams[i].credit == Some(0) {
+                i += 1;
+                continue;
+            }
+            let (response, end) = match self.streams[i].stream.poll() {
+                Ok(Async::Ready(Some(value))) => (self.streams[i].frame(Ok(value), false), false),
+                Ok(Async::Ready(None)) => (self.streams[i].frame(Ok(vec![]), true), true),
+                Err(err) => (self.streams[i].frame(Err(err), true), true),
+                Ok(Async::NotReady) => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let bytes = encode(format, response);
+            let bytes = match self.framing {
+                Some(ref framing) => framing.seal(bytes),
+                None => bytes,
+            };
+            write_package(&self.write_buffer, &bytes);
+            self.keepalive.active();
+            if end {
+                let id = self.streams[i].id;
+                self.finish_stream(id);
+            }
         }
     }
+
+    /// 流式响应结束或者被客户端取消
+    fn finish_stream(&mut self, id: u32) {
+        self.streams.retain(|stream| stream.id != id);
+        self.incoming.retain(|incoming| incoming.id != id);
+    }
+
+    /// 把客户端的消息交给函数，并归还相应的额度
+    fn poll_incoming(&mut self) {
+        let mut windows = Vec::new();
+        for incoming in self.incoming.iter_mut() {
+            let credit = incoming.forward();
+            if credit > 0 {
+                windows.push(Control::Window { id: incoming.id, credit });
+            }
+        }
+        self.incoming.retain(|incoming| !incoming.is_finished());
+        // 没有握手的连接不使用流控
+        if let Some(ref framing) = self.framing {
+            for window in windows {
+                write_package(&self.write_buffer, &framing.seal_control(window));
+            }
+        }
+    }
+
+    /// 握手的响应仍用原来的格式编码，之后的请求改用客户端选择的格式，每一帧带标志字节。
+    /// 客户端提出的压缩算法在服务端也启用时被接受，校验和总是按客户端的要求启用
+    fn handshake(&mut self, format: Format, request: Request) -> Vec<u8> {
+        let handshake: Handshake = match request.params.first().map(|param| format.deserialize(param)) {
+            Some(Ok(handshake)) => handshake,
+            _ => return encode(format, Response::err(request, Error::ParamDeserializeFail)),
+        };
+        let compression = self.compression.clone()
+            .filter(|c| handshake.compression == Some(c.algorithm()) && c.algorithm().supported());
+        let accepted = Handshake {
+            format: handshake.format,
+            compression: compression.as_ref().map(Compression::algorithm),
+            checksum: handshake.checksum,
+        };
+        let result = format.serialize(&accepted).unwrap();
+        let context = self.context.take().unwrap();
+        self.context = Some(context.with_format(handshake.format));
+        self.framing = Some(Framing::new(compression).checksum(handshake.checksum));
+        encode(format, Response::from(request, result))
+    }
 }
 
+impl Drop for ServerPackages {
+    fn drop(&mut self) {
+        self.connections.set(self.connections.get() - 1);
+        if let Some(ref context) = self.context {
+            context.cancellation().cancel();
+        }
+    }
+}
 
+/// 函数的返回值无法用连接的格式表示时（例如没有使用传入的 serializer），以错误响应代替
+fn encode(format: Format, response: Response) -> Vec<u8> {
+    match response.encode(format) {
+        Ok(bytes) => bytes,
+        Err(_) => Response { result: Err(Error::ResultDeserializeFail), ..response }.encode(format).unwrap(),
+    }
+}
+
+/// 到单个服务端节点的连接，产出收到的响应
 pub struct ClientPackages {
-    socket: TcpStream,
+    connecting: Option<Connecting>,
+    socket: Option<Box<dyn Socket>>,
     read_buffer: BytesMut,
     write_buffer: RefCell<BytesMut>,
-    receiver: Arc<Receiver<(Request, Box<FnMut(&Rc<BincodeSerializer>, &Response) + Send>)>>,
-    request_map: HashMap<u32, (Request, Box<FnMut(&Rc<BincodeSerializer>, &Response) + Send>)>,
-    serializer: Rc<BincodeSerializer>,
-    stoping: bool,
+    request_map: HashSet<u32>,
+    format: Format,
+    /// 收到握手响应之前，服务端仍使用 bincode，并且不带标志字节
+    handshaken: bool,
+    compression: Option<Compression>,
+    checksum: bool,
+    /// 握手请求之后发出的帧都带标志字节（和校验和），服务端接受压缩后才压缩
+    framing: Framing,
+    keepalive: Keepalive,
+    /// 服务端授予的双向流式调用的额度，由 `ClientDispatcher` 取走
+    granted: Vec<(u32, u32)>,
+    max_frame_size: usize,
 }
 
 impl Stream for ClientPackages {
-    type Item = BytesMut;
+    type Item = Response;
     type Error = io::Error;
 
-    fn poll(&mut self) -> Result<Async<Option<<Self as Stream>::Item>>, <Self as Stream>::Error> {
-//        let _ = self.poll_flush()?;
-        if self.receive().is_none() {
-            self.stoping = true;
-            return Ok(Async::NotReady);
-        }
-        if self.stoping && self.request_map.len() == 0 {
-            return Ok(Async::Ready(None));
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Some(mut connecting) = self.connecting.take() {
+            match connecting.poll()? {
+                Async::Ready(socket) => {
+                    self.socket = Some(socket);
+                    self.keepalive.received();
+                }
+                Async::NotReady => {
+                    self.connecting = Some(connecting);
+                    return Ok(Async::NotReady);
+                }
+            }
         }
-        let sock_closed = self.fill_read_buf()?.is_ready();
-        if self.read_buffer.len() < 4 {
-            if sock_closed {
-                return Ok(Async::Ready(None));
+        let socket = self.socket.as_mut().unwrap();
+        let _ = poll_flush(socket, &self.write_buffer)?;
+        let sock_closed = fill_read_buf(socket, &mut self.read_buffer, 4 + self.max_frame_size)?.is_ready();
+        while let Some(package) = split_package(&mut self.read_buffer, self.max_frame_size)? {
+            self.keepalive.received();
+            let (format, package) = if self.handshaken {
+                match self.framing.open(&package)? {
+                    Frame::Data(package) => (self.format, package),
+                    Frame::Control(Control::Ping) => {
+                        write_package(&self.write_buffer, &self.framing.seal_control(Control::Pong));
+                        continue;
+                    }
+                    Frame::Control(Control::Window { id, credit }) => {
+                        self.granted.push((id, credit));
+                        continue;
+                    }
+                    Frame::Control(Control::Pong) | Frame::Control(Control::Cancel { .. }) => continue,
+                }
+            } else {
+                (Format::Bincode, package.to_vec())
+            };
+            let response = match Response::decode(format, &package) {
+                Ok(t) => t,
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            };
+            if response.id == HANDSHAKE_ID {
+                let accepted: Handshake = match response.result {
+                    Ok(ref bytes) => Format::Bincode.deserialize(bytes)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?,
+                    Err(ref error) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("handshake failed: {:?}", error))),
+                };
+                if accepted.compression.is_some() {
+                    self.framing = Framing::new(self.compression.take()).checksum(self.checksum);
+                }
+                self.handshaken = true;
+                continue;
+            }
+            if response.id == u32::MAX {
+                println!("ERR:服务器反序列化失败");
+                continue;
+            }
+            // 流式响应在结束帧之后才不再等待
+            let expected = if response.is_end() {
+                self.request_map.remove(&response.id)
             } else {
-                return Ok(Async::NotReady);
+                self.request_map.contains(&response.id)
+            };
+            if expected {
+                return Ok(Async::Ready(Some(response)));
             }
         }
-        let mut package_length = [0; 4];
-        for i in 0..4 {
-            package_length[i] = self.read_buffer[i];
+        if sock_closed {
+            return Ok(Async::Ready(None));
         }
-        let mut rdr = Cursor::new(package_length);
-        let package_length = rdr.read_u32::<BigEndian>().unwrap() as usize;
-        if self.read_buffer.len() < 4 + package_length {
-            return Ok(Async::NotReady);
+        if self.keepalive.poll(true)? {
+            write_package(&self.write_buffer, &self.framing.seal_control(Control::Ping));
         }
-        let mut package = self.read_buffer.split_to(4 + package_length);
-        let package = package.split_off(4);
-        self.process(&package);
-        return Ok(Async::NotReady);
+        let _ = poll_flush(socket, &self.write_buffer)?;
+        Ok(Async::NotReady)
     }
 }
 
 impl ClientPackages {
-    fn new(socket: TcpStream,
-           receiver: Arc<Receiver<(Request, Box<FnMut(&Rc<BincodeSerializer>, &Response) + Send>)>>,
-           serializer: Rc<BincodeSerializer>) -> Self {
-        Self {
-            socket,
+    /// 握手请求最先发出，之后的请求直接使用 `format` 编码，不必等待握手响应
+    fn connect(connecting: Connecting, format: Format, compression: Option<Compression>, checksum: bool, keepalive: Keepalive) -> Self {
+        let handshake = Handshake {
+            format,
+            compression: compression.as_ref().map(Compression::algorithm).filter(|a| a.supported()),
+            checksum,
+        };
+        let packages = Self {
+            connecting: Some(connecting),
+            socket: None,
             read_buffer: BytesMut::new(),
             write_buffer: RefCell::new(BytesMut::new()),
+            request_map: HashSet::new(),
+            format,
+            handshaken: false,
+            compression,
+            checksum,
+            framing: Framing::new(None).checksum(checksum),
+            keepalive,
+            granted: Vec::new(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        };
+        let handshake = Request {
+            id: HANDSHAKE_ID,
+            name: String::from(HANDSHAKE),
+            params: vec![Format::Bincode.serialize(&handshake).unwrap()],
+            headers: Headers::new(),
+            one_way: false,
+            stream: None,
+        };
+        write_package(&packages.write_buffer, &handshake.encode(Format::Bincode).unwrap());
+        packages
+    }
+
+    fn is_connected(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// 发送缓冲区中还没有写入连接的字节数
+    fn buffered(&self) -> usize {
+        self.write_buffer.borrow().len()
+    }
+
+    /// 将请求写入发送缓冲区，连接建立后随下一次 poll 发出。参数不是合法的 JSON 时失败
+    fn send(&mut self, request: &Request) -> Result<(), Error> {
+        let bytes = request.encode(self.format).map_err(|_| Error::ParamDeserializeFail)?;
+        write_package(&self.write_buffer, &self.framing.seal(bytes));
+        if !request.one_way {
+            self.request_map.insert(request.id);
+        }
+        Ok(())
+    }
+
+    fn control(&mut self, control: Control) {
+        write_package(&self.write_buffer, &self.framing.seal_control(control));
+    }
+}
+
+struct Endpoint {
+    addr: Address,
+    packages: Option<ClientPackages>,
+    failures: u32,
+    ejected: Option<Timeout>,
+    probing: bool,
+    /// 已从服务发现结果中移除，等在途请求完成后关闭
+    removed: bool,
+}
+
+impl Endpoint {
+    fn new(addr: Address) -> Self {
+        Self {
+            addr,
+            packages: None,
+            failures: 0,
+            ejected: None,
+            probing: false,
+            removed: false,
+        }
+    }
+
+    fn available(&self) -> bool {
+        !self.removed && self.ejected.is_none() && !self.probing
+    }
+
+    fn in_flight(&self) -> usize {
+        self.packages.as_ref().map_or(0, |p| p.request_map.len())
+    }
+}
+
+/// 客户端内部的反射请求使用的 id，用户请求的 id 不会达到这个值
+const REFLECTION_ID: u32 = u32::MAX - 1;
+
+struct PendingCall {
+    request: Request,
+    key: Option<u64>,
+    callback: Callback,
+    /// 已经尝试的次数
+    attempts: u32,
+    /// 请求在途的节点和发出时间，对冲请求会同时发往多个节点
+    in_flight: Vec<(Address, Instant)>,
+    /// 到期后发送下一个对冲请求
+    hedge: Option<Timeout>,
+    hedges: u32,
+    /// 已经收到流式响应的帧或者是双向流式调用，不再对冲或重试
+    streaming: bool,
+    /// 双向流式调用中客户端发送消息的额度，调用结束时关闭
+    window: Option<Arc<Window>>,
+    /// 到期后调用以 `Error::DeadlineExceeded` 结束，包括等待重试期间
+    deadline: Option<Timeout>,
+}
+
+impl PendingCall {
+    fn new(request: Request, key: Option<u64>, callback: Callback) -> Self {
+        Self {
+            request,
+            key,
+            callback,
+            attempts: 1,
+            in_flight: Vec::new(),
+            hedge: None,
+            hedges: 0,
+            streaming: false,
+            window: None,
+            deadline: None,
+        }
+    }
+}
+
+impl Drop for PendingCall {
+    fn drop(&mut self) {
+        if let Some(ref window) = self.window {
+            window.close();
+        }
+    }
+}
+
+/// 在 NET 层线程中运行：接收客户端发来的调用，选择节点发送，并把响应交给回调
+struct ClientDispatcher {
+    handle: Handle,
+    receiver: Receiver<Command>,
+    service: String,
+    resolver: Box<dyn Resolver + Send>,
+    refresh: Interval,
+    endpoints: Vec<Endpoint>,
+    balancer: Box<dyn Balancer + Send>,
+    ejection: Ejection,
+    retry_policy: RetryPolicy,
+    circuits: Option<Circuits>,
+    latencies: Option<LatencyTracker>,
+    transport: Box<dyn Transport + Send>,
+    /// 服务端通过反射声明的幂等函数
+    idempotent: HashSet<String>,
+    calls: HashMap<u32, PendingCall>,
+    /// 等待退避时间结束后重试的调用
+    delayed: Vec<(Timeout, PendingCall)>,
+    format: Format,
+    compression: Option<Compression>,
+    checksum: bool,
+    credentials: Option<Box<dyn CredentialProvider + Send>>,
+    heartbeat: Option<Heartbeat>,
+    write_buffer_limit: usize,
+    max_frame_size: usize,
+    /// 发送缓冲区已满，暂停从队列中取出调用
+    paused: bool,
+    stopping: bool,
+}
+
+impl ClientDispatcher {
+    fn new(handle: Handle, client: Client, receiver: Receiver<Command>) -> Self {
+        let builder = client.builder;
+        let refresh = Interval::new(builder.refresh_interval, &handle).unwrap();
+        let mut dispatcher = Self {
+            handle,
             receiver,
-            request_map: HashMap::new(),
-            serializer,
-            stoping: false,
+            service: builder.service,
+            resolver: builder.resolver,
+            refresh,
+            endpoints: Vec::new(),
+            balancer: builder.balancer,
+            ejection: builder.ejection,
+            retry_policy: builder.retry_policy,
+            circuits: builder.circuit_breaker.map(Circuits::new),
+            latencies: builder.hedging_policy.map(LatencyTracker::new),
+            transport: builder.transport,
+            idempotent: HashSet::new(),
+            calls: HashMap::new(),
+            delayed: Vec::new(),
+            format: builder.format,
+            compression: builder.compression,
+            checksum: builder.checksum,
+            credentials: builder.credentials,
+            heartbeat: builder.heartbeat,
+            write_buffer_limit: builder.write_buffer_limit,
+            max_frame_size: builder.max_frame_size,
+            paused: false,
+            stopping: false,
+        };
+        dispatcher.resolve();
+        dispatcher
+    }
+
+    /// 重新解析节点列表：新节点加入，消失的节点不再分配请求，解析失败时保留原列表
+    fn resolve(&mut self) {
+        let addrs = match self.resolver.resolve(&self.service) {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                println!("ERR:服务发现失败 {}: {}", self.service, e);
+                return;
+            }
+        };
+        for endpoint in self.endpoints.iter_mut() {
+            endpoint.removed = !addrs.contains(&endpoint.addr);
+        }
+        for addr in addrs {
+            if !self.endpoints.iter().any(|e| e.addr == addr) {
+                self.endpoints.push(Endpoint::new(addr));
+            }
         }
     }
-    fn fill_read_buf(&mut self) -> Result<Async<()>, io::Error> {
-        loop {
-            self.read_buffer.reserve(1024);
-            let n = try_ready!(self.socket.read_buf(&mut self.read_buffer));
-            if n == 0 {
-                return Ok(Async::Ready(()));
+
+    fn views(&self) -> Vec<EndpointView> {
+        self.endpoints.iter().map(|endpoint| EndpointView {
+            addr: endpoint.addr.clone(),
+            in_flight: endpoint.in_flight(),
+            available: endpoint.available(),
+        }).collect()
+    }
+
+    /// 建立连接，并首先发送反射请求以获知哪些函数是幂等的
+    fn connect(&self, addr: &Address) -> ClientPackages {
+        let connecting = self.transport.connect(addr, &self.handle);
+        let keepalive = Keepalive::new(self.handle.clone(), self.heartbeat, None);
+        let mut packages = ClientPackages::connect(connecting, self.format, self.compression.clone(), self.checksum, keepalive);
+        packages.max_frame_size = self.max_frame_size;
+        packages.send(&Request {
+            id: REFLECTION_ID,
+            name: String::from(reflection::FUNCTIONS),
+            params: vec![],
+            headers: self.credentials.as_ref().map_or_else(Headers::new, |c| c.headers(reflection::FUNCTIONS)),
+            one_way: false,
+            stream: None,
+        }).unwrap();
+        packages
+    }
+
+    /// 为调用选择一个尚未发送过的节点并发送
+    fn send(&mut self, call: &mut PendingCall) -> Result<(), Error> {
+        let mut views = self.views();
+        let mut error = Error::NoAvailableEndpoint;
+        for view in views.iter_mut() {
+            if call.in_flight.iter().any(|(addr, _)| *addr == view.addr) {
+                view.available = false;
             }
         }
+        if let Some(circuits) = self.circuits.as_mut() {
+            for view in views.iter_mut().filter(|v| v.available) {
+                if !circuits.allow(&view.addr, &call.request.name) {
+                    view.available = false;
+                    error = Error::CircuitOpen;
+                }
+            }
+        }
+        let index = match self.balancer.pick(&views, call.key) {
+            Some(index) => index,
+            None => return Err(error),
+        };
+        let addr = self.endpoints[index].addr.clone();
+        if self.endpoints[index].packages.is_none() {
+            let packages = self.connect(&addr);
+            self.endpoints[index].packages = Some(packages);
+        }
+        if let Some(credentials) = self.credentials.as_ref() {
+            call.request.headers.extend(credentials.headers(&call.request.name));
+        }
+        self.endpoints[index].packages.as_mut().unwrap().send(&call.request)?;
+        if call.request.one_way {
+            return Ok(());
+        }
+        if let Some(circuits) = self.circuits.as_mut() {
+            circuits.dispatched(&addr, &call.request.name);
+        }
+        call.in_flight.push((addr, Instant::now()));
+        Ok(())
     }
 
-    fn write(&self, line: &[u8]) {
-        let mut write_buffer = self.write_buffer.borrow_mut();
-        write_buffer.reserve(line.len());
-        write_buffer.put(line);
+    /// 幂等函数在有多个可用节点时，等待一段时间后发送对冲请求
+    fn schedule_hedge(&mut self, call: &mut PendingCall) {
+        call.hedge = None;
+        let delay = match self.latencies {
+            Some(ref latencies) if call.hedges < latencies.max_hedges() => latencies.delay(&call.request.name),
+            _ => return,
+        };
+        let available = self.endpoints.iter().filter(|e| e.available()).count();
+        if available <= call.in_flight.len() || !self.idempotent.contains(&call.request.name) || call.streaming {
+            return;
+        }
+        call.hedge = Some(Timeout::new(delay, &self.handle).unwrap());
+        // 新的定时器需要被 poll 一次才会唤醒当前任务
+        task::current().notify();
+    }
+
+    /// 按请求头中的剩余时间为新的调用设置截止时间
+    fn pending(&self, request: Request, key: Option<u64>, callback: Callback) -> PendingCall {
+        let deadline = context::deadline(&request.headers);
+        let mut call = PendingCall::new(request, key, callback);
+        call.deadline = deadline.map(|deadline| Timeout::new_at(deadline, &self.handle).unwrap());
+        call
     }
 
-    fn poll_flush(&mut self) -> Poll<(), io::Error> {
-        let mut write_buffer = self.write_buffer.borrow_mut();
-        while !write_buffer.is_empty() {
-            let n = try_ready!(self.socket.write_buf(&mut (&*write_buffer).into_buf()));
-            assert!(n > 0);
-            let _ = write_buffer.split_to(n);
+    fn dispatch(&mut self, mut call: PendingCall) {
+        match self.send(&mut call) {
+            // 单向调用写入连接的发送缓冲区即完成
+            Ok(()) if call.request.one_way => {
+                let response = Response::from(call.request.clone(), vec![]);
+                (call.callback)(&self.format, &response);
+            }
+            Ok(()) => {
+                self.schedule_hedge(&mut call);
+                self.calls.insert(call.request.id, call);
+            }
+            Err(error) => {
+                let response = Response::err(call.request.clone(), error);
+                self.finish(call, &response, false);
+            }
         }
-        Ok(Async::Ready(()))
     }
 
-    fn process(&mut self, package: &BytesMut) {
-        let serializer = &self.serializer;
-        let response: Response = match serializer.deserialize(&package[..]) {
-            Ok(t) => t,
-            Err(_) => { panic!("deserialize fail"); }
+    /// 调用结束：可以重试的失败按退避时间重新分配节点，否则交给回调。
+    /// `sent` 为 false 表示请求确定没有发到服务端，此时非幂等函数也可以重试
+    fn finish(&mut self, mut call: PendingCall, response: &Response, sent: bool) {
+        if let Err(ref error) = response.result {
+            let safe = !call.streaming && (!sent || self.idempotent.contains(&call.request.name));
+            if safe && call.attempts < self.retry_policy.max_attempts() && self.retry_policy.is_retryable(error) {
+                call.attempts += 1;
+                call.hedges = 0;
+                let mut delay = self.retry_policy.delay(call.attempts);
+                // 服务端限流时至少等待它建议的时间
+                if let Error::ResourceExhausted { retry_after_ms } = *error {
+                    delay = delay.max(Duration::from_millis(retry_after_ms));
+                }
+                let delay = Timeout::new(delay, &self.handle).unwrap();
+                self.delayed.push((delay, call));
+                task::current().notify();
+                return;
+            }
+        }
+        (call.callback)(&self.format, response);
+    }
+
+    /// 收到 `addr` 上的结果。调用的其他对冲请求仍在途时忽略失败的结果，
+    /// 否则以此结果为准，取消其余请求
+    fn complete(&mut self, addr: Address, id: u32, result: Result<Vec<u8>, Error>, stream: Option<StreamFrame>, sent: bool) {
+        if id == REFLECTION_ID {
+            if let Ok(ref bytes) = result {
+                let functions: Vec<FunctionInfo> = self.format.deserialize(bytes).unwrap_or_default();
+                for function in functions {
+                    if function.idempotent {
+                        self.idempotent.insert(function.name);
+                    }
+                }
+            }
+            return;
+        }
+        let mut call = match self.calls.remove(&id) {
+            Some(call) => call,
+            None => return,
         };
-        if response.id == u32::MAX {
-            println!("ERR:服务器反序列化失败");
+        if let Some(position) = call.in_flight.iter().position(|(a, _)| *a == addr) {
+            let (_, started) = call.in_flight.remove(position);
+            let latency = started.elapsed();
+            if let Some(circuits) = self.circuits.as_mut() {
+                circuits.record(&addr, &call.request.name, result.as_ref().err(), latency);
+            }
+            // 流的持续时间不能作为对冲的延迟
+            if let (Some(latencies), true) = (self.latencies.as_mut(), result.is_ok() && !call.streaming) {
+                latencies.record(&call.request.name, latency);
+            }
+        }
+        if result.is_err() && !call.in_flight.is_empty() {
+            self.calls.insert(id, call);
             return;
         }
-        {
-            let (_request, callback) = self.request_map.get_mut(&response.id).unwrap();
-            callback(serializer, &response);
+        self.cancel(&mut call);
+        let response = Response {
+            id,
+            name: call.request.name.clone(),
+            result,
+            stream,
+        };
+        self.finish(call, &response, sent);
+    }
+
+    /// 流式响应的中间帧直接交给回调。收到第一帧时取消其他节点上的对冲请求
+    fn deliver(&mut self, addr: &Address, response: Response) {
+        let id = response.id;
+        // 普通调用只取第一个值，服务端不必再产出之后的值
+        if self.calls.get(&id).is_some_and(|call| call.request.stream.is_none()) {
+            if let Some(packages) = self.packages(addr) {
+                packages.request_map.remove(&id);
+                packages.control(Control::Cancel { id });
+            }
+            self.complete(addr.clone(), id, response.result, None, true);
+            return;
+        }
+        let mut call = match self.calls.remove(&id) {
+            Some(call) => call,
+            None => return,
+        };
+        if !call.streaming {
+            call.streaming = true;
+            let position = call.in_flight.iter().position(|(a, _)| a == addr);
+            let streaming = position.map(|position| call.in_flight.remove(position));
+            self.cancel(&mut call);
+            call.in_flight.extend(streaming);
+        }
+        (call.callback)(&self.format, &response);
+        self.calls.insert(id, call);
+    }
+
+    /// 双向流式调用中的消息发往调用所在的节点，调用已经结束时丢弃
+    fn forward(&mut self, request: Request) {
+        let addr = match self.calls.get(&request.id).and_then(|call| call.in_flight.first()) {
+            Some((addr, _)) => addr.clone(),
+            None => return,
+        };
+        if let Some(packages) = self.packages(&addr) {
+            let _ = packages.send(&request);
+        }
+    }
+
+    /// 把客户端的额度或取消发往流式调用所在的节点
+    fn control(&mut self, id: u32, control: Control) {
+        let addrs: Vec<Address> = match self.calls.get(&id) {
+            Some(call) => call.in_flight.iter().map(|(addr, _)| addr.clone()).collect(),
+            None => return,
+        };
+        for addr in addrs {
+            if let Some(packages) = self.packages(&addr) {
+                packages.control(control);
+            }
+        }
+    }
+
+    /// 调用方丢弃了流式响应，不再交给回调
+    fn abort(&mut self, id: u32) {
+        if let Some(mut call) = self.calls.remove(&id) {
+            self.cancel(&mut call);
         }
-        self.request_map.remove(&response.id);
     }
 
-    fn receive(&mut self) -> Option<()> {
+    fn packages(&mut self, addr: &Address) -> Option<&mut ClientPackages> {
+        self.endpoints.iter_mut().find(|e| e.addr == *addr).and_then(|e| e.packages.as_mut())
+    }
+
+    /// 取消调用仍在途的对冲请求，之后到达的响应会被丢弃；流式调用同时通知服务端丢弃流
+    fn cancel(&mut self, call: &mut PendingCall) {
+        call.hedge = None;
+        let (id, streaming) = (call.request.id, call.request.stream.is_some());
+        for (addr, _) in call.in_flight.drain(..) {
+            if let Some(packages) = self.packages(&addr) {
+                packages.request_map.remove(&id);
+                if streaming {
+                    packages.control(Control::Cancel { id });
+                }
+            }
+            if let Some(circuits) = self.circuits.as_mut() {
+                circuits.cancelled(&addr, &call.request.name);
+            }
+        }
+    }
+
+    /// 连接失败或断开：在途请求以 `error` 失败，连续失败次数达到上限则摘除节点
+    fn fail_endpoint(&mut self, index: usize, error: Error) {
+        let (addr, failed, sent) = {
+            let endpoint = &mut self.endpoints[index];
+            endpoint.probing = false;
+            endpoint.failures += 1;
+            if endpoint.failures >= self.ejection.max_failures {
+                endpoint.ejected = Some(Timeout::new(self.ejection.duration, &self.handle).unwrap());
+                task::current().notify();
+            }
+            match endpoint.packages.take() {
+                Some(packages) => {
+                    let sent = packages.is_connected();
+                    (endpoint.addr.clone(), packages.request_map, sent)
+                }
+                None => (endpoint.addr.clone(), HashSet::new(), false),
+            }
+        };
+        for id in failed {
+            self.complete(addr.clone(), id, Err(error.clone()), None, sent);
+        }
+    }
+
+    fn poll_endpoint(&mut self, index: usize) {
+        let mut probe = false;
+        if let Some(ejected) = self.endpoints[index].ejected.as_mut() {
+            match ejected.poll() {
+                Ok(Async::NotReady) => return,
+                _ => probe = true,
+            }
+        }
+        if probe {
+            let packages = self.connect(&self.endpoints[index].addr);
+            let endpoint = &mut self.endpoints[index];
+            endpoint.ejected = None;
+            endpoint.probing = true;
+            endpoint.packages = Some(packages);
+        }
         loop {
-            if let Ok(result) = self.receiver.try_recv() {
-                if result.0.id == u32::MAX && &result.0.name == "stop" {
-                    return None;
+            let result = match self.endpoints[index].packages.as_mut() {
+                Some(packages) => packages.poll(),
+                None => return,
+            };
+            match result {
+                Ok(Async::Ready(Some(response))) => {
+                    self.endpoints[index].failures = 0;
+                    let addr = self.endpoints[index].addr.clone();
+                    if response.is_end() {
+                        self.complete(addr, response.id, response.result, response.stream, true);
+                    } else {
+                        self.deliver(&addr, response);
+                    }
+                }
+                Ok(Async::NotReady) => break,
+                Ok(Async::Ready(None)) => {
+                    // 没有在途请求的连接被服务端关闭（例如空闲超时）不算失败，下次调用时重新连接
+                    if self.endpoints[index].in_flight() == 0 && !self.endpoints[index].probing {
+                        self.endpoints[index].packages = None;
+                    } else {
+                        self.fail_endpoint(index, Error::ConnectionFail);
+                    }
+                    return;
+                }
+                Err(e) => {
+                    let error = if frame::is_corrupt(&e) { Error::CorruptFrame } else { Error::ConnectionFail };
+                    self.fail_endpoint(index, error);
+                    return;
+                }
+            }
+        }
+        let granted = self.endpoints[index].packages.as_mut().map_or_else(Vec::new, |p| p.granted.split_off(0));
+        for (id, credit) in granted {
+            if let Some(window) = self.calls.get(&id).and_then(|call| call.window.as_ref()) {
+                window.grant(credit);
+            }
+        }
+        let endpoint = &mut self.endpoints[index];
+        if endpoint.probing && endpoint.packages.as_ref().is_some_and(|p| p.is_connected()) {
+            endpoint.probing = false;
+            endpoint.failures = 0;
+        }
+    }
+
+    /// 所有连接的发送缓冲区合计达到上限
+    fn saturated(&self) -> bool {
+        let buffered: usize = self.endpoints.iter().filter_map(|e| e.packages.as_ref()).map(ClientPackages::buffered).sum();
+        buffered >= self.write_buffer_limit
+    }
+
+    fn poll_delayed(&mut self) {
+        let mut i = 0;
+        while i < self.delayed.len() {
+            match self.delayed[i].0.poll() {
+                Ok(Async::NotReady) => i += 1,
+                _ => {
+                    let (_, call) = self.delayed.swap_remove(i);
+                    self.dispatch(call);
+                }
+            }
+        }
+    }
+
+    /// 超过截止时间的调用不再重试，在途请求通知服务端取消，之后到达的响应被丢弃
+    fn poll_deadlines(&mut self) {
+        let expired: Vec<u32> = self.calls.iter_mut().filter_map(|(id, call)| {
+            match call.deadline.as_mut().map(|deadline| deadline.poll()) {
+                None | Some(Ok(Async::NotReady)) => None,
+                _ => Some(*id),
+            }
+        }).collect();
+        for id in expired {
+            let mut call = self.calls.remove(&id).unwrap();
+            // 流式调用由 `cancel` 通知服务端
+            if call.request.stream.is_none() {
+                self.control(id, Control::Cancel { id });
+            }
+            self.cancel(&mut call);
+            self.expire(call);
+        }
+        let mut i = 0;
+        while i < self.delayed.len() {
+            match self.delayed[i].1.deadline.as_mut().map(|deadline| deadline.poll()) {
+                None | Some(Ok(Async::NotReady)) => i += 1,
+                _ => {
+                    let (_, call) = self.delayed.swap_remove(i);
+                    self.expire(call);
                 }
-                let bytes = self.serializer.serialize(&result.0).unwrap();
-                self.request_map.insert(result.0.id, result);
-                let mut length = vec![];
-                length.write_u32::<BigEndian>(bytes.len() as u32).unwrap();
-                self.write(&length);
-                self.write(&bytes);
-                self.poll_flush().unwrap();
+            }
+        }
+    }
+
+    fn expire(&mut self, mut call: PendingCall) {
+        let response = Response::err(call.request.clone(), Error::DeadlineExceeded);
+        (call.callback)(&self.format, &response);
+    }
+
+    fn poll_hedges(&mut self) {
+        let ready: Vec<u32> = self.calls.iter_mut().filter_map(|(id, call)| {
+            match call.hedge.as_mut().map(|hedge| hedge.poll()) {
+                None | Some(Ok(Async::NotReady)) => None,
+                _ => Some(*id),
+            }
+        }).collect();
+        for id in ready {
+            let mut call = self.calls.remove(&id).unwrap();
+            call.hedges += 1;
+            if self.send(&mut call).is_ok() {
+                self.schedule_hedge(&mut call);
             } else {
-                return Some(());
+                call.hedge = None;
             }
-        };
+            self.calls.insert(id, call);
+        }
     }
 }
 
-trait PackageHandler {
-    fn handle(&self, package: BytesMut);
+impl Future for ClientDispatcher {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        self.paused = false;
+        loop {
+            // 队列中的调用留在有界队列里，调用方随之等待或被拒绝
+            if self.saturated() {
+                self.paused = true;
+                break;
+            }
+            match self.receiver.poll()? {
+                Async::Ready(Some(Command::Call(request, key, callback))) => {
+                    let call = self.pending(request, key, callback);
+                    self.dispatch(call);
+                }
+                Async::Ready(Some(Command::Open(request, key, callback, window))) => {
+                    let mut call = self.pending(request, key, callback);
+                    // 已经发出的消息无法重放
+                    call.streaming = true;
+                    call.window = Some(window);
+                    self.dispatch(call);
+                }
+                Async::Ready(Some(Command::Message(request))) => self.forward(request),
+                Async::Ready(Some(Command::Window(id, credit))) => self.control(id, Control::Window { id, credit }),
+                Async::Ready(Some(Command::Cancel(id))) => self.abort(id),
+                Async::Ready(Some(Command::Stop)) | Async::Ready(None) => {
+                    self.stopping = true;
+                    break;
+                }
+                Async::NotReady => break,
+            }
+        }
+        while let Ok(Async::Ready(Some(()))) = self.refresh.poll() {
+            self.resolve();
+        }
+        self.poll_delayed();
+        self.poll_deadlines();
+        self.poll_hedges();
+        for index in 0..self.endpoints.len() {
+            self.poll_endpoint(index);
+        }
+        self.endpoints.retain(|e| !e.removed || e.in_flight() > 0);
+        // 连接写出数据后缓冲区有了空间，再次 poll 以取出队列中的调用
+        if self.paused && !self.saturated() {
+            task::current().notify();
+        }
+        if self.stopping && self.calls.is_empty() && self.delayed.is_empty() {
+            return Ok(Async::Ready(()));
+        }
+        Ok(Async::NotReady)
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use net::Server;
+    use net::{Server, Client, Command, Callback};
     use std::net::TcpStream;
     use std::thread;
     use std::io::Write;
@@ -335,18 +1398,52 @@ mod tests {
     use std::net::SocketAddr;
     use byteorder::BigEndian;
     use byteorder::WriteBytesExt;
-    use serialization::BincodeSerializer;
+    use serialization::{BincodeSerializer, Format};
     use common::Request;
+    use auth::Headers;
     use serialization::Serializer;
     use server::Processes;
     use std::rc::Rc;
-    use net::Client;
-    use std::sync::mpsc;
-    use std::sync::mpsc::Sender;
+    use futures::sync::mpsc;
     use common::Response;
-    use std::sync::Arc;
-    use std::u32;
     use std::time::Duration;
+    use client::MyRPCClientBuilder;
+
+    /// 每次读取都填满缓冲区，模拟一直发送数据的对端
+    struct Endless;
+
+    impl Read for Endless {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            for byte in buf.iter_mut() {
+                *byte = 0;
+            }
+            Ok(buf.len())
+        }
+    }
+
+    impl ::tokio_io::AsyncRead for Endless {}
+
+    #[test]
+    fn read_limit_test() {
+        use bytes::{BufMut, BytesMut};
+        use futures::{future, Future};
+        use net::{fill_read_buf, split_package};
+
+        let mut buffer = BytesMut::new();
+        buffer.put(&[0, 0, 0, 3, 1, 2, 3, 0][..]);
+        assert_eq!(Some(&[1u8, 2, 3][..]), split_package(&mut buffer, 3).unwrap().as_ref().map(|p| &p[..]));
+        assert_eq!(None, split_package(&mut buffer, 3).unwrap());
+        // 声明的长度超过上限时不等待剩余的数据
+        let mut buffer = BytesMut::new();
+        buffer.put(&[0xff, 0xff, 0xff, 0xf0][..]);
+        assert!(split_package(&mut buffer, 16 << 20).is_err());
+
+        // 读缓冲区达到上限后停止读取
+        let mut buffer = BytesMut::new();
+        let polled = future::lazy(|| Ok::<_, ()>(fill_read_buf(&mut Endless, &mut buffer, 1 << 16))).wait().unwrap();
+        assert!(polled.unwrap().is_not_ready());
+        assert!(buffer.len() >= 1 << 16 && buffer.len() < (1 << 16) + 4096);
+    }
 
     //    #[test]
     #[allow(dead_code)]
@@ -371,6 +1468,9 @@ mod tests {
             name: String::from("print"),
             params: vec![BincodeSerializer::new().serialize(&123).unwrap(),
                          BincodeSerializer::new().serialize(&456).unwrap()],
+            headers: Headers::new(),
+            one_way: false,
+            stream: None,
         };
         let send = BincodeSerializer::new().serialize(&request).unwrap();
 
@@ -378,18 +1478,18 @@ mod tests {
         wtr.write_u32::<BigEndian>(send.len() as u32).unwrap();
         println!("{:?}", wtr);
         let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
-        let mut stream = TcpStream::connect(&addr).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
         loop {
             let msg = wtr.as_ref();
             thread::sleep(Duration::from_secs(1));
-            let _ = stream.write(&msg).unwrap();
+            stream.write_all(msg).unwrap();
             thread::sleep(Duration::from_secs(1));
             let msg = send.as_ref();
-            let _ = stream.write(&msg).unwrap();
+            stream.write_all(msg).unwrap();
             thread::sleep(Duration::from_secs(1));
             let mut buffer = [0; 10];
 
-            stream.read(&mut buffer).unwrap();
+            stream.read_exact(&mut buffer).unwrap();
             println!("{:?}", buffer);
         }
     }
@@ -397,31 +1497,27 @@ mod tests {
     //    #[test]
     #[allow(dead_code)]
     fn client_test() {
-        let channel = mpsc::channel();
-        let sender: Sender<(Request, Box<FnMut(&Rc<BincodeSerializer>, &Response) + Send>)> = channel.0;
-        let receiver = channel.1;
+        let (mut sender, receiver) = mpsc::channel(16);
         let req = Request {
             id: 2,
             name: String::from("print"),
             params: vec![BincodeSerializer::new().serialize(&123).unwrap(),
                          BincodeSerializer::new().serialize(&456).unwrap()],
+            headers: Headers::new(),
+            one_way: false,
+            stream: None,
         };
-        let callback = |_: &Rc<BincodeSerializer>, r: &Response| { println!("{:?}", r) };
+        let callback = |_: &Format, r: &Response| { println!("{:?}", r) };
         let handler = thread::spawn(move || {
             let addr = "127.0.0.1:8080".parse().unwrap();
-            let client = Client::new(addr, Rc::new(BincodeSerializer::new()));
-            client.start(&Arc::new(receiver));
+            let client = Client::new(MyRPCClientBuilder::new(vec![addr]));
+            client.start(receiver);
         });
-        sender.send((req, Box::new(callback))).unwrap();
+        let callback: Callback = Box::new(callback);
+        sender.try_send(Command::Call(req, None, callback)).unwrap();
         thread::sleep(Duration::from_secs(1));
 
-        let req = Request {
-            id: u32::MAX,
-            name: String::from("stop"),
-            params: vec![BincodeSerializer::new().serialize(&123).unwrap(),
-                         BincodeSerializer::new().serialize(&456).unwrap()],
-        };
-        sender.send((req, Box::new(callback))).unwrap();
+        sender.try_send(Command::Stop).unwrap();
         handler.join().unwrap();
     }
-}
\ No newline at end of file
+}
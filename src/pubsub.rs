@@ -0,0 +1,153 @@
+use error::{Error, Result};
+use futures::sync::mpsc::{self, Receiver, Sender};
+use futures::{Async, Poll, Stream};
+use serde::Serialize;
+use serialization::{Format, Serializer};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use stream::ResponseStream;
+
+/// 服务端内置的订阅函数名，参数是主题，返回该主题之后发布的值的流
+pub const SUBSCRIBE: &str = "$subscribe";
+
+/// 每个订阅者最多缓冲多少个还没有发给客户端的值，超过时订阅以
+/// `Error::ResourceExhausted` 结束，客户端可以重新订阅
+const SUBSCRIBER_BUFFER: usize = 64;
+
+type Topics = Arc<Mutex<HashMap<String, Vec<Subscriber>>>>;
+
+/// 向订阅了主题的客户端推送值，由 `MyRPCServer::publisher` 得到。
+/// 可以克隆后在函数中或者其他线程中使用，服务端停止之后发布的值被丢弃
+///
+/// ```
+/// use myrpc4rs::server::MyRPCServer;
+/// use std::thread;
+///
+/// let myrpc = MyRPCServer::new("127.0.0.1:8080".parse().unwrap());
+/// let publisher = myrpc.publisher();
+/// thread::spawn(move || {
+///     publisher.publish("road/42", &(42u32, 0.8f64));
+/// });
+/// ```
+#[derive(Clone, Default)]
+pub struct Publisher {
+    topics: Topics,
+}
+
+struct Subscriber {
+    format: Format,
+    sender: Sender<Vec<u8>>,
+    /// 订阅因为客户端读得太慢而被结束，同时用来找到订阅对应的订阅者
+    lagged: Arc<AtomicBool>,
+}
+
+impl Publisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 不阻塞，返回收到这个值的订阅者数
+    pub fn publish<T: Serialize>(&self, topic: &str, value: &T) -> usize {
+        let mut topics = self.topics.lock().unwrap();
+        let subscribers = match topics.get_mut(topic) {
+            Some(subscribers) => subscribers,
+            None => return 0,
+        };
+        // 每种格式只序列化一次
+        let mut encoded: Vec<(Format, Vec<u8>)> = Vec::new();
+        let mut delivered = 0;
+        subscribers.retain_mut(|subscriber| {
+            let bytes = match encoded.iter().find(|(format, _)| *format == subscriber.format) {
+                Some((_, bytes)) => bytes.clone(),
+                None => match Serializer::serialize(&subscriber.format, value) {
+                    Ok(bytes) => {
+                        encoded.push((subscriber.format, bytes.clone()));
+                        bytes
+                    }
+                    Err(_) => return true,
+                },
+            };
+            match subscriber.sender.try_send(bytes) {
+                Ok(()) => {
+                    delivered += 1;
+                    true
+                }
+                Err(ref e) if e.is_full() => {
+                    println!("ERR:订阅 {} 的客户端读取太慢，结束订阅", topic);
+                    subscriber.lagged.store(true, Ordering::SeqCst);
+                    false
+                }
+                Err(_) => false,
+            }
+        });
+        if subscribers.is_empty() {
+            topics.remove(topic);
+        }
+        delivered
+    }
+
+    /// 主题当前的订阅者数
+    pub fn subscribers(&self, topic: &str) -> usize {
+        self.topics.lock().unwrap().get(topic).map_or(0, Vec::len)
+    }
+
+    pub(crate) fn subscribe(&self, format: Format, topic: String) -> ResponseStream {
+        let (sender, receiver) = mpsc::channel(SUBSCRIBER_BUFFER);
+        let lagged = Arc::new(AtomicBool::new(false));
+        let subscriber = Subscriber {
+            format,
+            sender,
+            lagged: Arc::clone(&lagged),
+        };
+        self.topics.lock().unwrap().entry(topic.clone()).or_default().push(subscriber);
+        Box::new(Subscription {
+            receiver,
+            lagged,
+            topics: Arc::clone(&self.topics),
+            topic,
+        })
+    }
+}
+
+/// 订阅在服务端的一端，作为流式响应发给客户端。drop 时（取消订阅、连接断开或服务端停止）移除订阅者
+struct Subscription {
+    receiver: Receiver<Vec<u8>>,
+    lagged: Arc<AtomicBool>,
+    topics: Topics,
+    topic: String,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut topics = self.topics.lock().unwrap();
+        if let Some(subscribers) = topics.get_mut(&self.topic) {
+            subscribers.retain(|subscriber| !Arc::ptr_eq(&subscriber.lagged, &self.lagged));
+            if subscribers.is_empty() {
+                topics.remove(&self.topic);
+            }
+        }
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, Error> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(None)) | Err(()) if self.lagged.load(Ordering::SeqCst) => {
+                Err(Error::ResourceExhausted { retry_after_ms: 0 })
+            }
+            Ok(Async::Ready(value)) => Ok(Async::Ready(value)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(()) => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+/// 订阅的参数
+pub(crate) fn topic(format: &Format, params: &[Vec<u8>]) -> Result<String> {
+    let param = params.first().ok_or(Error::ParamDeserializeFail)?;
+    format.deserialize(param).map_err(|_| Error::ParamDeserializeFail)
+}
@@ -0,0 +1,9 @@
+/// 服务端内置的反射函数名，返回所有已注册函数的 `FunctionInfo`
+pub const FUNCTIONS: &str = "$functions";
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct FunctionInfo {
+    pub name: String,
+    /// 幂等函数失败时可以被客户端自动重试
+    pub idempotent: bool,
+}
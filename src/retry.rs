@@ -0,0 +1,100 @@
+use error::Error;
+use std::cmp;
+use std::mem;
+use std::time::Duration;
+
+/// 客户端重试策略
+///
+/// 只有幂等函数（服务端通过 `Registration::idempotent` 声明，并经反射告知客户端）
+/// 或确定没有发到服务端的请求才会被重试。
+///
+/// ```
+/// use myrpc4rs::retry::RetryPolicy;
+/// use myrpc4rs::error::Error;
+/// use std::time::Duration;
+///
+/// let policy = RetryPolicy::new(3)
+///     .backoff(Duration::from_millis(100), Duration::from_secs(1))
+///     .retry_on(vec![Error::ConnectionFail]);
+/// assert_eq!(Duration::from_millis(200), policy.delay(3));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    retryable: Vec<Error>,
+}
+
+impl RetryPolicy {
+    /// 最多尝试 `max_attempts` 次（包括第一次），默认在连接失败或没有可用节点时重试
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            retryable: vec![Error::ConnectionFail, Error::NoAvailableEndpoint],
+        }
+    }
+
+    /// 不重试
+    pub fn none() -> Self {
+        Self::new(1)
+    }
+
+    /// 指数退避：第 n 次重试前等待 `initial * 2^(n-1)`，最多等待 `max`
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// 可以重试的错误，只比较枚举成员，不比较携带的数据
+    pub fn retry_on(mut self, retryable: Vec<Error>) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    pub fn is_retryable(&self, error: &Error) -> bool {
+        self.retryable.iter().any(|e| mem::discriminant(e) == mem::discriminant(error))
+    }
+
+    /// 第 `attempt` 次尝试（从 2 开始）之前的等待时间
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let shift = cmp::min(attempt.saturating_sub(2), 16);
+        cmp::min(self.initial_backoff * (1 << shift), self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use retry::RetryPolicy;
+    use error::Error;
+    use std::time::Duration;
+
+    #[test]
+    fn retry_policy_test() {
+        let policy = RetryPolicy::new(5).backoff(Duration::from_millis(10), Duration::from_millis(50));
+        assert_eq!(Duration::from_millis(10), policy.delay(2));
+        assert_eq!(Duration::from_millis(20), policy.delay(3));
+        assert_eq!(Duration::from_millis(40), policy.delay(4));
+        assert_eq!(Duration::from_millis(50), policy.delay(5));
+        assert_eq!(Duration::from_millis(50), policy.delay(100));
+        assert!(policy.is_retryable(&Error::ConnectionFail));
+        assert!(!policy.is_retryable(&Error::FunctionNotFound));
+
+        let policy = policy.retry_on(vec![Error::FunctionNotFound]);
+        assert!(policy.is_retryable(&Error::FunctionNotFound));
+        assert!(!policy.is_retryable(&Error::ConnectionFail));
+    }
+}
@@ -1,19 +1,20 @@
 use bincode::serialize as bincode_serialize;
 use bincode::deserialize as bincode_deserialize;
+use serde_json;
 use std::error;
 use serde::Serialize;
 use serde::Deserialize;
 
-type Error = Box<error::Error>;
+type Error = Box<dyn error::Error>;
 type Result<T> = ::std::result::Result<T, Error>;
 
 pub trait Serializer {
-    fn serialize<T: ?Sized>(&self, value: &T) -> Result<Vec<u8>>
-        where T: Serialize;
+    fn serialize<T>(&self, value: &T) -> Result<Vec<u8>>
+        where T: Serialize + ?Sized;
     fn deserialize<'a, T>(&self, bytes: &'a [u8]) -> Result<T>
         where T: Deserialize<'a>;
 }
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct BincodeSerializer;
 
 impl BincodeSerializer {
@@ -23,9 +24,9 @@ impl BincodeSerializer {
 }
 
 impl Serializer for BincodeSerializer {
-    fn serialize<T: ?Sized>(&self, value: &T) -> Result<Vec<u8>>
-        where T: Serialize {
-        Ok(Vec::from(bincode_serialize(value)?))
+    fn serialize<T>(&self, value: &T) -> Result<Vec<u8>>
+        where T: Serialize + ?Sized {
+        Ok(bincode_serialize(value)?)
     }
 
     fn deserialize<'a, T>(&self, bytes: &'a [u8]) -> Result<T>
@@ -33,4 +34,52 @@ impl Serializer for BincodeSerializer {
         Ok(bincode_deserialize(bytes)?)
     }
 
-}
\ No newline at end of file
+}
+
+/// 浏览器等非 Rust 客户端使用的 JSON 格式
+#[derive(Clone, Default)]
+pub struct JsonSerializer;
+
+impl JsonSerializer {
+    pub fn new() -> Self {
+        Self{}
+    }
+}
+
+impl Serializer for JsonSerializer {
+    fn serialize<T>(&self, value: &T) -> Result<Vec<u8>>
+        where T: Serialize + ?Sized {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn deserialize<'a, T>(&self, bytes: &'a [u8]) -> Result<T>
+        where T: Deserialize<'a>{
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// 连接上使用的序列化格式，由传输层决定，服务端函数用它解析参数和序列化返回值
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Format {
+    #[default]
+    Bincode,
+    Json,
+}
+
+impl Serializer for Format {
+    fn serialize<T>(&self, value: &T) -> Result<Vec<u8>>
+        where T: Serialize + ?Sized {
+        match *self {
+            Format::Bincode => BincodeSerializer.serialize(value),
+            Format::Json => JsonSerializer.serialize(value),
+        }
+    }
+
+    fn deserialize<'a, T>(&self, bytes: &'a [u8]) -> Result<T>
+        where T: Deserialize<'a>{
+        match *self {
+            Format::Bincode => BincodeSerializer.deserialize(bytes),
+            Format::Json => JsonSerializer.deserialize(bytes),
+        }
+    }
+}
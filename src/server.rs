@@ -1,15 +1,44 @@
-use std::net::SocketAddr;
-use serialization::BincodeSerializer;
-use std::collections::HashMap;
+use address::Address;
+use serialization::{BincodeSerializer, Format};
+use std::collections::{HashMap, HashSet};
 use error::Result;
 use error::Error;
 use net::Server;
 use std::cell::RefCell;
 use std::rc::Rc;
+use serialization::Serializer;
+use reflection::{self, FunctionInfo};
+use context::{self, Identity, RequestContext};
+use std::time::Duration;
+use auth::{Authenticator, Headers};
+use acl::AccessPolicy;
+use interceptor::{Interceptor, Next};
+use limit::{Limiter, Limits, Permit};
+use stream::{RequestStream, ResponseStream};
+use pubsub::{self, Publisher};
+use futures::sync::mpsc::UnboundedSender;
+use futures::{Poll, Stream};
+#[cfg(feature = "tls")]
+use tls::{ServerTlsConfig, TlsTransport};
+use transport::Transport;
+use compression::Compression;
+use keepalive::Heartbeat;
 
 
+type Function = Box<dyn FnMut(&Format, &RequestContext, &Vec<Vec<u8>>) -> Result<Vec<u8>>>;
+type StreamFunction = Box<dyn FnMut(&Format, &RequestContext, &Vec<Vec<u8>>, RequestStream) -> Result<ResponseStream>>;
+type AccessRule = Box<dyn Fn(&RequestContext) -> bool>;
+
 pub struct Processes {
-    function_map: RefCell<HashMap<String, Box<FnMut(&Rc<BincodeSerializer>, &Vec<Vec<u8>>) -> Result<Vec<u8>>>>>,
+    function_map: RefCell<HashMap<String, Function>>,
+    stream_map: RefCell<HashMap<String, StreamFunction>>,
+    idempotent: RefCell<HashSet<String>>,
+    access_rules: RefCell<HashMap<String, AccessRule>>,
+    authenticator: RefCell<Option<Box<dyn Authenticator>>>,
+    access_policy: RefCell<Option<AccessPolicy>>,
+    interceptors: RefCell<Vec<Box<dyn Interceptor>>>,
+    limiter: RefCell<Option<Limiter>>,
+    publisher: Publisher,
     serializer: Rc<BincodeSerializer>,
 }
 
@@ -17,23 +46,163 @@ impl Processes {
     pub fn new(serializer: Rc<BincodeSerializer>) -> Self {
         Self {
             function_map: RefCell::new(HashMap::new()),
+            stream_map: RefCell::new(HashMap::new()),
+            idempotent: RefCell::new(HashSet::new()),
+            access_rules: RefCell::new(HashMap::new()),
+            authenticator: RefCell::new(None),
+            access_policy: RefCell::new(None),
+            interceptors: RefCell::new(Vec::new()),
+            limiter: RefCell::new(None),
+            publisher: Publisher::new(),
             serializer,
         }
     }
 
-    pub fn insert_function<F: 'static>(&self, name: String, function: F)
-        where F: FnMut(&Rc<BincodeSerializer>, &Vec<Vec<u8>>) -> Result<Vec<u8>> {
+    pub fn insert_function<F>(&self, name: String, mut function: F)
+        where F: 'static + FnMut(&Format, &Vec<Vec<u8>>) -> Result<Vec<u8>> {
+        self.insert_function_with_context(name, move |serializer, _, params| function(serializer, params));
+    }
+
+    pub fn insert_function_with_context<F>(&self, name: String, function: F)
+        where F: 'static + FnMut(&Format, &RequestContext, &Vec<Vec<u8>>) -> Result<Vec<u8>> {
         self.function_map.borrow_mut().insert(name, Box::new(function));
     }
 
-    pub fn execute_function(&self, name: &str, params: &Vec<Vec<u8>>) -> Result<Vec<u8>> {
+    pub fn insert_stream_function<F>(&self, name: String, mut function: F)
+        where F: 'static + FnMut(&Format, &RequestContext, &Vec<Vec<u8>>) -> Result<ResponseStream> {
+        self.insert_bidi_function(name, move |serializer, context, params, _| function(serializer, context, params));
+    }
+
+    pub fn insert_bidi_function<F>(&self, name: String, function: F)
+        where F: 'static + FnMut(&Format, &RequestContext, &Vec<Vec<u8>>, RequestStream) -> Result<ResponseStream> {
+        self.stream_map.borrow_mut().insert(name, Box::new(function));
+    }
+
+    pub fn is_stream_function(&self, name: &str) -> bool {
+        name == pubsub::SUBSCRIBE || self.stream_map.borrow().contains_key(name)
+    }
+
+    pub fn set_idempotent(&self, name: &str) {
+        self.idempotent.borrow_mut().insert(String::from(name));
+    }
+
+    pub fn set_access_rule<F>(&self, name: &str, rule: F)
+        where F: 'static + Fn(&RequestContext) -> bool {
+        self.access_rules.borrow_mut().insert(String::from(name), Box::new(rule));
+    }
+
+    pub fn set_authenticator<A: 'static + Authenticator>(&self, authenticator: A) {
+        *self.authenticator.borrow_mut() = Some(Box::new(authenticator));
+    }
+
+    pub fn set_access_policy(&self, policy: AccessPolicy) {
+        *self.access_policy.borrow_mut() = Some(policy);
+    }
+
+    pub fn add_interceptor<I: 'static + Interceptor>(&self, interceptor: I) {
+        self.interceptors.borrow_mut().push(Box::new(interceptor));
+    }
+
+    pub fn set_limits(&self, limits: Limits) {
+        *self.limiter.borrow_mut() = Some(Limiter::new(limits));
+    }
+
+    /// 没有设置 `Authenticator` 时返回 None，不改变调用方身份
+    pub fn authenticate(&self, context: &RequestContext, name: &str, headers: &Headers) -> Result<Option<Identity>> {
+        match *self.authenticator.borrow() {
+            Some(ref authenticator) => authenticator.authenticate(context, name, headers).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// 所有注册的函数，反射请求只返回其中访问规则允许调用方调用的函数
+    pub fn functions(&self) -> Vec<FunctionInfo> {
+        let idempotent = self.idempotent.borrow();
+        let names: Vec<String> = self.function_map.borrow().keys().chain(self.stream_map.borrow().keys()).cloned().collect();
+        let mut functions: Vec<FunctionInfo> = names.into_iter().map(|name| FunctionInfo {
+            idempotent: idempotent.contains(&name),
+            name,
+        }).collect();
+        functions.sort_by(|a, b| a.name.cmp(&b.name));
+        functions
+    }
+
+    /// 检查访问规则和限流，然后依次经过拦截器并执行函数
+    pub fn execute_function(&self, context: &RequestContext, name: &str, params: &Vec<Vec<u8>>) -> Result<Vec<u8>> {
+        // 名额在函数返回后归还
+        let _permit = match name {
+            reflection::FUNCTIONS => None,
+            _ => self.check(context, name)?,
+        };
+        self.intercept(0, context, name, params)
+    }
+
+    pub(crate) fn intercept(&self, index: usize, context: &RequestContext, name: &str, params: &Vec<Vec<u8>>) -> Result<Vec<u8>> {
+        let interceptors = self.interceptors.borrow();
+        match interceptors.get(index) {
+            Some(interceptor) => interceptor.intercept(context, name, params, Next::new(self, index + 1)),
+            None => self.dispatch(context, name, params),
+        }
+    }
+
+    fn dispatch(&self, context: &RequestContext, name: &str, params: &Vec<Vec<u8>>) -> Result<Vec<u8>> {
+        if name == reflection::FUNCTIONS {
+            let functions: Vec<FunctionInfo> = self.functions().into_iter()
+                .filter(|function| self.is_allowed(context, &function.name))
+                .collect();
+            return Ok(context.format().serialize(&functions).unwrap());
+        }
         let mut function = self.function_map.borrow_mut();
         let function = function.get_mut(name);
-        let result = match function {
-            Some(function) => function(&self.serializer, params),
+        match function {
+            Some(function) => context::enter(context, || function(&context.format(), context, params)),
             None => Err(Error::FunctionNotFound)
+        }
+    }
+
+    /// 流式函数不经过拦截器，访问规则和限流与普通函数相同，并发名额在流结束后归还。
+    /// `messages` 是双向流式调用中客户端发送的消息
+    pub fn execute_stream(&self, context: &RequestContext, name: &str, params: &Vec<Vec<u8>>, messages: RequestStream) -> Result<ResponseStream> {
+        let permit = self.check(context, name)?;
+        let stream = if name == pubsub::SUBSCRIBE {
+            let topic = pubsub::topic(&context.format(), params)?;
+            self.publisher.subscribe(context.format(), topic)
+        } else {
+            let mut functions = self.stream_map.borrow_mut();
+            let function = functions.get_mut(name).ok_or(Error::FunctionNotFound)?;
+            context::enter(context, || function(&context.format(), context, params, messages))?
         };
-        result
+        Ok(match permit {
+            Some(permit) => Box::new(PermittedStream { stream, _permit: permit }),
+            None => stream,
+        })
+    }
+
+    /// 截止时间、访问规则和限流
+    fn check(&self, context: &RequestContext, name: &str) -> Result<Option<Permit>> {
+        if context.remaining() == Some(Duration::from_secs(0)) {
+            return Err(Error::DeadlineExceeded);
+        }
+        if !self.is_allowed(context, name) {
+            return Err(Error::PermissionDenied);
+        }
+        match *self.limiter.borrow_mut() {
+            Some(ref mut limiter) => limiter.acquire(context, name).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn is_allowed(&self, context: &RequestContext, name: &str) -> bool {
+        if let Some(ref policy) = *self.access_policy.borrow() {
+            if !policy.is_allowed(context.identity(), name) {
+                return false;
+            }
+        }
+        self.access_rules.borrow().get(name).is_none_or(|rule| rule(context))
+    }
+
+    pub fn publisher(&self) -> Publisher {
+        self.publisher.clone()
     }
 
     pub fn get_serializer(&self) -> &BincodeSerializer {
@@ -41,6 +210,38 @@ impl Processes {
     }
 }
 
+/// 持有并发名额的流，流被丢弃时归还
+struct PermittedStream {
+    stream: ResponseStream,
+    _permit: Permit,
+}
+
+impl Stream for PermittedStream {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, Error> {
+        self.stream.poll()
+    }
+}
+
+/// 停止正在运行的服务端，可以在其他线程中使用
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    sender: UnboundedSender<()>,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new(sender: UnboundedSender<()>) -> Self {
+        Self { sender }
+    }
+
+    /// `start_server` 随后返回，已建立的连接被关闭
+    pub fn shutdown(&self) {
+        let _ = self.sender.unbounded_send(());
+    }
+}
+
 pub struct MyRPCServer {
     serializer: BincodeSerializer,
     processes: Rc<Processes>,
@@ -48,17 +249,164 @@ pub struct MyRPCServer {
 }
 
 impl MyRPCServer {
-    pub fn new(socket_addr: SocketAddr) -> Self {
+    pub fn new(address: Address) -> Self {
         Self {
             serializer: BincodeSerializer::new(),
             processes: Rc::new(Processes::new(Rc::new(BincodeSerializer::new()))),
-            server: Server::new(socket_addr),
+            server: Server::new(address),
         }
     }
 
-    pub fn register_function<F: 'static>(&self, name: String, function: F)
-        where F: FnMut(&Rc<BincodeSerializer>, &Vec<Vec<u8>>) -> Result<Vec<u8>> {
-        self.processes.insert_function(name, function);
+    /// 只接受 TLS 连接，在当前传输层之上加密
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, tls: ServerTlsConfig) -> Self {
+        let inner = self.server.take_transport();
+        self.server.set_transport(Box::new(TlsTransport::new(inner).server(tls)));
+        self
+    }
+
+    /// 自定义传输层，默认按地址类型使用 TCP、Unix 域套接字或进程内传输
+    pub fn transport<T: 'static + Transport>(mut self, transport: T) -> Self {
+        self.server.set_transport(Box::new(transport));
+        self
+    }
+
+    /// 客户端也启用压缩时，超过阈值的响应被压缩
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.server.set_compression(compression);
+        self
+    }
+
+    /// 同时保持的连接数上限，达到上限后新的连接被立即关闭
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.server.set_max_connections(max_connections);
+        self
+    }
+
+    /// 超过 `idle_timeout` 没有收到请求的连接被关闭，心跳不算
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.server.set_idle_timeout(idle_timeout);
+        self
+    }
+
+    /// 每个连接的发送缓冲区上限，默认 1 MiB。客户端读取响应太慢、积压达到上限时，暂停读取该连接上的请求
+    pub fn write_buffer_limit(mut self, write_buffer_limit: usize) -> Self {
+        self.server.set_write_buffer_limit(write_buffer_limit);
+        self
+    }
+
+    /// 收到的帧的长度上限，默认 16 MiB。客户端声明更长的帧时关闭连接，读缓冲区也不会超过这个大小
+    pub fn max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.server.set_max_frame_size(max_frame_size);
+        self
+    }
+
+    /// 在握手完成的连接上发送心跳，超时没有收到客户端的任何帧时关闭连接
+    pub fn heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.server.set_heartbeat(heartbeat);
+        self
+    }
+
+    /// 执行函数（包括反射）之前校验请求的凭据，失败时返回 `Error::Unauthenticated`
+    pub fn authenticator<A: 'static + Authenticator>(self, authenticator: A) -> Self {
+        self.processes.set_authenticator(authenticator);
+        self
+    }
+
+    /// 执行函数之前按访问策略检查调用方身份，与 `Registration::access` 的规则同时生效
+    pub fn access_policy(self, policy: AccessPolicy) -> Self {
+        self.processes.set_access_policy(policy);
+        self
+    }
+
+    /// 添加拦截器，先添加的在外层
+    pub fn interceptor<I: 'static + Interceptor>(self, interceptor: I) -> Self {
+        self.processes.add_interceptor(interceptor);
+        self
+    }
+
+    /// 执行函数之前按调用方身份、对端 IP 和函数限流，并限制函数的并发执行次数
+    pub fn limits(self, limits: Limits) -> Self {
+        self.processes.set_limits(limits);
+        self
+    }
+
+    /// 同时在另一个地址上用 `transport` 接受连接，所有监听地址共享注册的函数
+    pub fn listen<T: 'static + Transport>(mut self, address: Address, transport: T) -> Self {
+        self.server.add_listener(address, Box::new(transport));
+        self
+    }
+
+    pub fn register_function<F>(&self, name: String, function: F) -> Registration<'_>
+        where F: 'static + FnMut(&Format, &Vec<Vec<u8>>) -> Result<Vec<u8>> {
+        self.processes.insert_function(name.clone(), function);
+        Registration { processes: &self.processes, name }
+    }
+
+    /// 处理函数可以通过 `RequestContext` 得知调用方的地址和 mTLS 身份
+    pub fn register_function_with_context<F>(&self, name: String, function: F) -> Registration<'_>
+        where F: 'static + FnMut(&Format, &RequestContext, &Vec<Vec<u8>>) -> Result<Vec<u8>> {
+        self.processes.insert_function_with_context(name.clone(), function);
+        Registration { processes: &self.processes, name }
+    }
+
+    /// 流式函数逐个产出返回值，客户端通过 `MyRPCCall::stream` 调用。拦截器不作用于流式函数
+    ///
+    /// ```no_run
+    /// # extern crate futures;
+    /// # extern crate myrpc4rs;
+    /// # use myrpc4rs::server::MyRPCServer;
+    /// # use myrpc4rs::serialization::Serializer;
+    /// # fn main() {
+    /// use futures::stream;
+    ///
+    /// let myrpc = MyRPCServer::new("127.0.0.1:8080".parse().unwrap());
+    /// myrpc.register_stream_function(String::from("countdown"), |serializer, _, params| {
+    ///     let from: u32 = serializer.deserialize(&params[0]).unwrap();
+    ///     let values: Vec<Vec<u8>> = (0..from).rev().map(|i| serializer.serialize(&i).unwrap()).collect();
+    ///     Ok(Box::new(stream::iter_ok(values)))
+    /// });
+    /// # }
+    /// ```
+    pub fn register_stream_function<F>(&self, name: String, function: F) -> Registration<'_>
+        where F: 'static + FnMut(&Format, &RequestContext, &Vec<Vec<u8>>) -> Result<ResponseStream> {
+        self.processes.insert_stream_function(name.clone(), function);
+        Registration { processes: &self.processes, name }
+    }
+
+    /// 双向流式函数在产出值的同时读取客户端发送的消息，客户端通过 `MyRPCCall::bidi` 调用。
+    /// 只返回一个值的双向流式函数即为客户端流式函数，例如分块上传
+    ///
+    /// ```no_run
+    /// # extern crate futures;
+    /// # extern crate myrpc4rs;
+    /// # use myrpc4rs::server::MyRPCServer;
+    /// # use myrpc4rs::serialization::Serializer;
+    /// # fn main() {
+    /// use futures::{Future, Stream};
+    ///
+    /// let myrpc = MyRPCServer::new("127.0.0.1:8080".parse().unwrap());
+    /// myrpc.register_bidi_function(String::from("upload"), |serializer, _, _, chunks| {
+    ///     let serializer = *serializer;
+    ///     let total = chunks.fold(0, |total, chunk| Ok::<_, myrpc4rs::error::Error>(total + chunk.len()));
+    ///     Ok(Box::new(total.map(move |total| serializer.serialize(&total).unwrap()).into_stream()))
+    /// });
+    /// # }
+    /// ```
+    pub fn register_bidi_function<F>(&self, name: String, function: F) -> Registration<'_>
+        where F: 'static + FnMut(&Format, &RequestContext, &Vec<Vec<u8>>, RequestStream) -> Result<ResponseStream> {
+        self.processes.insert_bidi_function(name.clone(), function);
+        Registration { processes: &self.processes, name }
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.server.shutdown_handle()
+    }
+
+    /// 向通过 `MyRPCClient::subscribe` 订阅了主题的客户端推送值。
+    /// 订阅按函数名 `pubsub::SUBSCRIBE` 经过访问策略和限流
+    pub fn publisher(&self) -> Publisher {
+        self.processes.publisher()
     }
 
     pub fn start_server(&mut self) {
@@ -70,14 +418,63 @@ impl MyRPCServer {
     }
 }
 
+/// `register_function` 的返回值，用于声明函数的属性
+///
+/// ```no_run
+/// # #[macro_use] extern crate myrpc4rs;
+/// # use myrpc4rs::server::MyRPCServer;
+/// # use myrpc4rs::serialization::Serializer;
+/// # fn main() {
+/// let mut myrpc = MyRPCServer::new("127.0.0.1:8080".parse().unwrap());
+/// myrpc_function!(myrpc,add,a<u32>,b<u32>,{
+///     a + b
+/// }).idempotent();
+/// # }
+/// ```
+pub struct Registration<'a> {
+    processes: &'a Processes,
+    name: String,
+}
+
+impl<'a> Registration<'a> {
+    /// 声明函数是幂等的，客户端通过反射得知后会在失败时自动重试
+    pub fn idempotent(self) -> Self {
+        self.processes.set_idempotent(&self.name);
+        self
+    }
+
+    /// 访问规则，返回 false 时拒绝调用并返回 `Error::PermissionDenied`
+    ///
+    /// ```no_run
+    /// # #[macro_use] extern crate myrpc4rs;
+    /// # use myrpc4rs::server::MyRPCServer;
+    /// # use myrpc4rs::serialization::Serializer;
+    /// # fn main() {
+    /// let mut myrpc = MyRPCServer::new("127.0.0.1:8080".parse().unwrap());
+    /// myrpc_function!(myrpc,reset,a<u32>,{
+    ///     a
+    /// }).access(|context| context.identity().is_some_and(|identity| identity.has_name("admin")));
+    /// # }
+    /// ```
+    pub fn access<F>(self, rule: F) -> Self
+        where F: 'static + Fn(&RequestContext) -> bool {
+        self.processes.set_access_rule(&self.name, rule);
+        self
+    }
+}
+
 #[macro_export]
 macro_rules! myrpc_function {
     ($myrpc_server:expr, $function_name:expr, $($param:ident<$t:ty>),+ , $myrpc_block:block) => {
         $myrpc_server.register_function(String::from(stringify!($function_name)), |serializer, process| {
             let mut _i = 0;
-            $(let $param:$t = serializer.deserialize(&process[_i]).unwrap();_i+=1;)+
+            // 参数个数或类型不符时返回错误，而不是让服务端线程 panic
+            $(let $param:$t = match process.get(_i).map(|param| serializer.deserialize(param)) {
+                Some(Ok(param)) => param,
+                _ => return Err($crate::error::Error::ParamDeserializeFail),
+            };_i+=1;)+
             Ok(serializer.serialize(&$myrpc_block).unwrap())
-        });
+        })
     }
 
 }
@@ -86,9 +483,17 @@ macro_rules! myrpc_function {
 mod tests {
     use server::Processes;
     use serialization::Serializer;
-    use serialization::BincodeSerializer;
+    use serialization::{BincodeSerializer, Format};
     use server::MyRPCServer;
+    use testing::{spawn_test_server, spawn_tcp_server};
     use std::rc::Rc;
+    use reflection::{self, FunctionInfo};
+    use context::{Identity, RequestContext};
+    use error::{Error, Result};
+    use acl::{AccessPolicy, Decision, Rule};
+    use limit::{Limits, RateLimit};
+    use client::MyRPCClient;
+    use std::thread;
 
     #[test]
     fn process_test() {
@@ -105,17 +510,372 @@ mod tests {
 
         let param1 = BincodeSerializer::new().serialize(&1).unwrap();
         let param2 = BincodeSerializer::new().serialize(&2).unwrap();
-        let result = processse.execute_function("test", &vec![param1, param2]);
-        assert_eq!(Ok(vec![1, 2, 3]), result)
+        let context = RequestContext::new("127.0.0.1:8080".parse().unwrap());
+        let result = processse.execute_function(&context, "test", &vec![param1, param2]);
+        assert_eq!(Ok(vec![3, 0, 0, 0]), result)
+    }
+
+    #[test]
+    fn reflection_test() {
+        let myrpc = MyRPCServer::new("127.0.0.1:8080".parse().unwrap());
+        myrpc_function!(myrpc,add,a<u32>,b<u32>,{
+            a + b
+        }).idempotent();
+        myrpc_function!(myrpc,print,a<u32>,{
+            println!("{}", a);
+        });
+        let context = RequestContext::new("127.0.0.1:8080".parse().unwrap());
+        let result = myrpc.processes.execute_function(&context, reflection::FUNCTIONS, &vec![]).unwrap();
+        let functions: Vec<FunctionInfo> = myrpc.get_serializer().deserialize(&result).unwrap();
+        assert_eq!(vec![FunctionInfo { name: String::from("add"), idempotent: true },
+                        FunctionInfo { name: String::from("print"), idempotent: false }], functions);
+    }
+
+    #[test]
+    fn format_test() {
+        let myrpc = MyRPCServer::new("127.0.0.1:8080".parse().unwrap());
+        myrpc_function!(myrpc,add,a<u32>,b<u32>,{
+            a + b
+        });
+        let context = RequestContext::new("127.0.0.1:8080".parse().unwrap()).with_format(Format::Json);
+        let params = vec![b"1".to_vec(), b"2".to_vec()];
+        assert_eq!(Ok(b"3".to_vec()), myrpc.processes.execute_function(&context, "add", &params));
+        let params = vec![b"1".to_vec(), b"\"2\"".to_vec()];
+        assert_eq!(Err(Error::ParamDeserializeFail), myrpc.processes.execute_function(&context, "add", &params));
+        assert_eq!(Err(Error::ParamDeserializeFail), myrpc.processes.execute_function(&context, "add", &vec![]));
+    }
+
+    #[test]
+    fn access_test() {
+        let myrpc = MyRPCServer::new("127.0.0.1:8080".parse().unwrap());
+        myrpc.register_function_with_context(String::from("whoami"), |serializer, context, _| {
+            let name = context.identity().and_then(|identity| identity.common_name()).unwrap_or("").to_string();
+            Ok(serializer.serialize(&name).unwrap())
+        }).access(|context| context.identity().is_some_and(|identity| identity.has_name("admin")));
+
+        let context = RequestContext::new("127.0.0.1:8080".parse().unwrap());
+        assert_eq!(Err(Error::PermissionDenied), myrpc.processes.execute_function(&context, "whoami", &vec![]));
+        let guest = context.clone().with_identity(Identity::new("CN=guest", vec![]));
+        assert_eq!(Err(Error::PermissionDenied), myrpc.processes.execute_function(&guest, "whoami", &vec![]));
+        let admin = context.with_identity(Identity::new("CN=admin", vec![]));
+        let result = myrpc.processes.execute_function(&admin, "whoami", &vec![]).unwrap();
+        assert_eq!("admin", myrpc.get_serializer().deserialize::<String>(&result).unwrap());
+    }
+
+    #[test]
+    fn access_policy_test() {
+        let policy = AccessPolicy::new("path_server", Decision::Deny)
+            .role("admin", vec![String::from("admin")])
+            .rule(Rule {
+                namespace: String::from("path_server"),
+                function: String::from("update_graph"),
+                allow: vec![String::from("role:admin")],
+                deny: vec![],
+            });
+        let myrpc = MyRPCServer::new("127.0.0.1:8080".parse().unwrap()).access_policy(policy);
+        myrpc_function!(myrpc,update_graph,a<u32>,{
+            a
+        });
+        let params = vec![myrpc.get_serializer().serialize(&1u32).unwrap()];
+        let guest = RequestContext::new("127.0.0.1:8080".parse().unwrap()).with_identity(Identity::named("guest"));
+        assert_eq!(Err(Error::PermissionDenied), myrpc.processes.execute_function(&guest, "update_graph", &params));
+        let admin = guest.with_identity(Identity::named("admin"));
+        assert!(myrpc.processes.execute_function(&admin, "update_graph", &params).is_ok());
+        // 反射只列出调用方有权限调用的函数
+        let result = myrpc.processes.execute_function(&admin, reflection::FUNCTIONS, &vec![]).unwrap();
+        let functions: Vec<FunctionInfo> = myrpc.get_serializer().deserialize(&result).unwrap();
+        assert_eq!(vec![FunctionInfo { name: String::from("update_graph"), idempotent: false }], functions);
+        let guest = admin.with_identity(Identity::named("guest"));
+        let result = myrpc.processes.execute_function(&guest, reflection::FUNCTIONS, &vec![]).unwrap();
+        let functions: Vec<FunctionInfo> = myrpc.get_serializer().deserialize(&result).unwrap();
+        assert!(functions.is_empty());
+    }
+
+    #[test]
+    fn limits_test() {
+        let limits = Limits::new().function("add", RateLimit::per_minute(1));
+        let myrpc = MyRPCServer::new("127.0.0.1:8080".parse().unwrap()).limits(limits);
+        myrpc_function!(myrpc,add,a<u32>,b<u32>,{
+            a + b
+        });
+        let serializer = BincodeSerializer::new();
+        let params = vec![serializer.serialize(&1u32).unwrap(), serializer.serialize(&2u32).unwrap()];
+        let context = RequestContext::new("127.0.0.1:8080".parse().unwrap());
+        assert!(myrpc.processes.execute_function(&context, "add", &params).is_ok());
+        match myrpc.processes.execute_function(&context, "add", &params) {
+            Err(Error::ResourceExhausted { retry_after_ms }) => assert!(retry_after_ms > 50000),
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert!(myrpc.processes.execute_function(&context, reflection::FUNCTIONS, &vec![]).is_ok());
+    }
+
+    #[test]
+    fn interceptor_test() {
+        use interceptor::{Interceptor, Next};
+        use std::cell::RefCell;
+
+        struct Recording(Rc<RefCell<Vec<String>>>);
+
+        impl Interceptor for Recording {
+            fn intercept(&self, context: &RequestContext, name: &str, params: &Vec<Vec<u8>>, next: Next) -> Result<Vec<u8>> {
+                self.0.borrow_mut().push(format!("before {}", name));
+                let result = next.run(context, name, params);
+                self.0.borrow_mut().push(format!("after {}", name));
+                result
+            }
+        }
+
+        struct Cached;
+
+        impl Interceptor for Cached {
+            fn intercept(&self, context: &RequestContext, name: &str, params: &Vec<Vec<u8>>, next: Next) -> Result<Vec<u8>> {
+                if name == "cached" {
+                    return Ok(BincodeSerializer::new().serialize(&42u32).unwrap());
+                }
+                next.run(context, name, params)
+            }
+        }
+
+        /// 把 `double` 改写为两个参数相同的 `add`
+        struct Rewrite;
+
+        impl Interceptor for Rewrite {
+            fn intercept(&self, context: &RequestContext, name: &str, params: &Vec<Vec<u8>>, next: Next) -> Result<Vec<u8>> {
+                match name {
+                    "double" => next.run(context, "add", &vec![params[0].clone(), params[0].clone()]),
+                    _ => next.run(context, name, params),
+                }
+            }
+        }
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let myrpc = MyRPCServer::new("127.0.0.1:8080".parse().unwrap())
+            .limits(Limits::new().function("cached", RateLimit::per_minute(2)))
+            .interceptor(Recording(Rc::clone(&log)))
+            .interceptor(Cached)
+            .interceptor(Rewrite);
+        myrpc_function!(myrpc,add,a<u32>,b<u32>,{
+            a + b
+        });
+        let serializer = BincodeSerializer::new();
+        let context = RequestContext::new("127.0.0.1:8080".parse().unwrap());
+        let params = vec![serializer.serialize(&3u32).unwrap()];
+        assert_eq!(Ok(serializer.serialize(&6u32).unwrap()), myrpc.processes.execute_function(&context, "double", &params));
+        assert_eq!(Ok(serializer.serialize(&42u32).unwrap()), myrpc.processes.execute_function(&context, "cached", &vec![]));
+        assert_eq!(Err(Error::FunctionNotFound), myrpc.processes.execute_function(&context, "missing", &vec![]));
+        assert_eq!(vec!["before double", "after double", "before cached", "after cached", "before missing", "after missing"],
+                   *log.borrow());
+
+        // 访问规则和限流在拦截器之外检查，缓存的结果不会交给被拒绝的调用方
+        myrpc.processes.set_access_rule("cached", |context| context.identity().is_some());
+        let guest = context.clone().with_identity(Identity::named("guest"));
+        assert_eq!(Err(Error::PermissionDenied), myrpc.processes.execute_function(&context, "cached", &vec![]));
+        assert!(myrpc.processes.execute_function(&guest, "cached", &vec![]).is_ok());
+        assert!(matches!(myrpc.processes.execute_function(&guest, "cached", &vec![]), Err(Error::ResourceExhausted { .. })));
+        // 被拒绝和被限流的调用没有到达拦截器
+        assert_eq!(8, log.borrow().len());
+    }
+
+    #[test]
+    fn keepalive_test() {
+        use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+        use common::{Handshake, Request, HANDSHAKE, HANDSHAKE_ID};
+        use keepalive::Heartbeat;
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+        use std::time::Duration;
+
+        fn read_frame(stream: &mut TcpStream) -> Option<Vec<u8>> {
+            let length = stream.read_u32::<BigEndian>().ok()?;
+            let mut frame = vec![0; length as usize];
+            stream.read_exact(&mut frame).ok()?;
+            Some(frame)
+        }
+
+        let (_server, address) = spawn_tcp_server(|myrpc| {
+            myrpc.max_connections(1)
+                .idle_timeout(Duration::from_millis(300))
+                .heartbeat(Heartbeat::new(Duration::from_millis(50)).timeout(Duration::from_millis(150)))
+        });
+        let mut stream = TcpStream::connect(address).unwrap();
+        let handshake = Handshake { format: Format::Bincode, compression: None, checksum: false };
+        let request = Request {
+            id: HANDSHAKE_ID,
+            name: String::from(HANDSHAKE),
+            params: vec![BincodeSerializer::new().serialize(&handshake).unwrap()],
+            headers: Default::default(),
+            one_way: false,
+            stream: None,
+        };
+        let request = BincodeSerializer::new().serialize(&request).unwrap();
+        stream.write_u32::<BigEndian>(request.len() as u32).unwrap();
+        stream.write_all(&request).unwrap();
+        assert!(read_frame(&mut stream).is_some());
+
+        // 达到连接数上限，新的连接被立即关闭
+        let mut rejected = TcpStream::connect(address).unwrap();
+        assert_eq!(0, rejected.read(&mut [0; 16]).unwrap());
+
+        // 握手之后收到心跳，不回复时超时关闭
+        assert_eq!(Some(vec![0x08, 0x01]), read_frame(&mut stream));
+        while read_frame(&mut stream).is_some() {}
+
+        // 没有握手的连接不发送心跳，空闲超时后关闭
+        let mut idle = TcpStream::connect(address).unwrap();
+        idle.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(0, idle.read(&mut [0; 16]).unwrap());
+    }
+
+    #[test]
+    fn backpressure_test() {
+        use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+        use common::Request;
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::{Duration, Instant};
+
+        let executed = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&executed);
+        let (_server, address) = spawn_tcp_server(move |myrpc| {
+            let myrpc = myrpc.write_buffer_limit(1 << 16);
+            myrpc.register_function(String::from("large"), move |_, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(vec![0u8; 1 << 20])
+            });
+            myrpc
+        });
+        let mut stream = TcpStream::connect(address).unwrap();
+        for id in 0..20 {
+            let request = Request { id, name: String::from("large"), params: vec![], headers: Default::default(), one_way: false, stream: None };
+            let request = BincodeSerializer::new().serialize(&request).unwrap();
+            stream.write_u32::<BigEndian>(request.len() as u32).unwrap();
+            stream.write_all(&request).unwrap();
+        }
+        // 不读取响应时，服务端在发送缓冲区积压后停止处理请求
+        let started = Instant::now();
+        let mut last = 0;
+        loop {
+            thread::sleep(Duration::from_millis(50));
+            let count = executed.load(Ordering::SeqCst);
+            if count > 0 && count == last {
+                break;
+            }
+            assert!(started.elapsed() < Duration::from_secs(5));
+            last = count;
+        }
+        assert!(last < 20);
+        for _ in 0..20 {
+            let length = stream.read_u32::<BigEndian>().unwrap();
+            let mut response = vec![0; length as usize];
+            stream.read_exact(&mut response).unwrap();
+        }
+        assert_eq!(20, executed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn max_frame_size_test() {
+        use byteorder::{BigEndian, WriteBytesExt};
+        use std::io::Read;
+        use std::net::TcpStream;
+        use std::time::Duration;
+
+        let (_server, address) = spawn_tcp_server(|myrpc| myrpc.max_frame_size(1 << 16));
+        // 声明过长的帧的连接被立即关闭，服务端不等待也不缓存帧的内容
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_u32::<BigEndian>(u32::MAX - 1).unwrap();
+        let closed = match stream.read(&mut [0; 16]) {
+            Ok(n) => n == 0,
+            Err(e) => e.kind() == ::std::io::ErrorKind::ConnectionReset,
+        };
+        assert!(closed);
+    }
+
+    #[test]
+    fn handshake_test() {
+        use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+        use common::{Handshake, Request, Response, HANDSHAKE, HANDSHAKE_ID};
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        fn send(stream: &mut TcpStream, flag: Option<u8>, request: &Request) {
+            let mut frame: Vec<u8> = flag.into_iter().collect();
+            frame.extend(BincodeSerializer::new().serialize(request).unwrap());
+            stream.write_u32::<BigEndian>(frame.len() as u32).unwrap();
+            stream.write_all(&frame).unwrap();
+        }
+
+        fn receive(stream: &mut TcpStream, flag: bool) -> Response {
+            let length = stream.read_u32::<BigEndian>().unwrap();
+            let mut frame = vec![0; length as usize];
+            stream.read_exact(&mut frame).unwrap();
+            let start = if flag { 1 } else { 0 };
+            BincodeSerializer::new().deserialize(&frame[start..]).unwrap()
+        }
+
+        let serializer = BincodeSerializer::new();
+        let handshake = Request {
+            id: HANDSHAKE_ID,
+            name: String::from(HANDSHAKE),
+            params: vec![serializer.serialize(&Handshake { format: Format::Bincode, compression: None, checksum: false }).unwrap()],
+            headers: Default::default(),
+            one_way: false,
+            stream: None,
+        };
+        let add = Request {
+            id: 1,
+            name: String::from("add"),
+            params: vec![serializer.serialize(&1u32).unwrap(), serializer.serialize(&2u32).unwrap()],
+            headers: Default::default(),
+            one_way: false,
+            stream: None,
+        };
+        let (_server, address) = spawn_tcp_server(|myrpc| {
+            myrpc_function!(myrpc,add,a<u32>,b<u32>,{
+                a + b
+            });
+            myrpc
+        });
+
+        // 握手之后不能再次握手
+        let mut stream = TcpStream::connect(address).unwrap();
+        send(&mut stream, None, &handshake);
+        assert!(receive(&mut stream, false).result.is_ok());
+        send(&mut stream, Some(0), &handshake);
+        assert_eq!(Err(Error::FunctionNotFound), receive(&mut stream, true).result);
+        send(&mut stream, Some(0), &add);
+        assert_eq!(Ok(serializer.serialize(&3u32).unwrap()), receive(&mut stream, true).result);
+
+        // 已经有过请求的连接不能再握手，帧格式不变
+        let mut stream = TcpStream::connect(address).unwrap();
+        send(&mut stream, None, &add);
+        assert_eq!(Ok(serializer.serialize(&3u32).unwrap()), receive(&mut stream, false).result);
+        send(&mut stream, None, &handshake);
+        assert_eq!(Err(Error::FunctionNotFound), receive(&mut stream, false).result);
+        send(&mut stream, None, &add);
+        assert_eq!(Ok(serializer.serialize(&3u32).unwrap()), receive(&mut stream, false).result);
     }
 
     #[test]
     fn myrpcserver_test() {
-        let mut myrpc = MyRPCServer::new("127.0.0.1:8080".parse().unwrap());
-        myrpc_function!(myrpc,test1,param1<u32>,param2<u32>,{
-            println!("{},{}",param1,param2);
-            param1+param2
+        let server = spawn_test_server(move || {
+            let myrpc = MyRPCServer::new("memory:myrpcserver_test".parse().unwrap());
+            myrpc_function!(myrpc,test1,param1<u32>,param2<u32>,{
+                println!("{},{}",param1,param2);
+                param1+param2
+            });
+            myrpc
         });
-        myrpc.start_server();
+        let mut client = MyRPCClient::new("memory:myrpcserver_test".parse().unwrap());
+        let serializer = BincodeSerializer::new();
+        let params = vec![serializer.serialize(&1u32).unwrap(), serializer.serialize(&2u32).unwrap()];
+        let response = client.call(String::from("test1"), params.clone()).sync();
+        assert_eq!(Ok(serializer.serialize(&3u32).unwrap()), response.result);
+
+        drop(server);
+        let response = client.call(String::from("test1"), params).sync();
+        assert_eq!(Err(Error::ConnectionFail), response.result);
     }
 }
\ No newline at end of file
@@ -0,0 +1,334 @@
+use auth::Headers;
+use client::Queue;
+use common::{Request, Response, StreamFrame};
+use error::{Error, Result};
+use futures::future;
+use futures::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver};
+use futures::{Async, Future, Poll, Stream};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serialization::{Format, Serializer};
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// 流式调用在每个方向上最多有多少个值已经发出、但还没有被对端取走，
+/// 取走一半之后对端以 `Control::Window` 授予新的额度
+pub(crate) const DEFAULT_WINDOW: u32 = 16;
+
+/// 流式函数返回的值序列，每一项是按连接格式序列化的值。流结束时服务端发送结束帧，
+/// 流产出错误时以该错误结束。服务端在 reactor 线程中 poll 它，发送缓冲区满或者
+/// 客户端没有取走已经发出的值时暂停 poll
+pub type ResponseStream = Box<dyn Stream<Item = Vec<u8>, Error = Error>>;
+
+/// 双向流式函数收到的客户端消息，每一项是按连接格式序列化的消息。
+/// 客户端在结束之前断开或取消调用时以 `Error::ConnectionFail` 结束
+pub type RequestStream = Box<dyn Stream<Item = Vec<u8>, Error = Error> + Send>;
+
+/// 在其他线程中逐个产出流式函数的值，最多缓冲 `buffer` 个还没有写出的值
+///
+/// ```
+/// use myrpc4rs::serialization::Format;
+/// use myrpc4rs::stream;
+/// use std::thread;
+///
+/// let (mut sender, stream) = stream::channel(&Format::Bincode, 16);
+/// thread::spawn(move || {
+///     for progress in 0..100u32 {
+///         // 客户端断开后不再继续
+///         if sender.send(&progress).is_err() {
+///             break;
+///         }
+///     }
+/// });
+/// ```
+pub fn channel(format: &Format, buffer: usize) -> (StreamSender, ResponseStream) {
+    let (sender, receiver) = mpsc::channel(buffer);
+    let stream = receiver.then(|item| item.unwrap_or(Err(Error::ConnectionFail)));
+    (StreamSender { format: *format, sender }, Box::new(stream))
+}
+
+/// `channel` 的发送端，drop 时流正常结束
+pub struct StreamSender {
+    format: Format,
+    sender: Sender<Result<Vec<u8>>>,
+}
+
+impl StreamSender {
+    /// 缓冲区满时阻塞，因此不能在服务端的 reactor 线程中调用。
+    /// 流已经被丢弃（客户端断开或服务端停止）时返回 `Error::ConnectionFail`
+    pub fn send<T: Serialize>(&mut self, value: &T) -> Result<()> {
+        let bytes = Serializer::serialize(&self.format, value).map_err(|_| Error::ResultDeserializeFail)?;
+        self.push(Ok(bytes))
+    }
+
+    /// 以错误结束流
+    pub fn fail(mut self, error: Error) {
+        let _ = self.push(Err(error));
+    }
+
+    fn push(&mut self, item: Result<Vec<u8>>) -> Result<()> {
+        let sender = &mut self.sender;
+        future::poll_fn(|| sender.poll_ready()).wait().map_err(|_| Error::ConnectionFail)?;
+        self.sender.try_send(item).map_err(|_| Error::ConnectionFail)
+    }
+}
+
+/// 客户端收到的流式响应，由 `MyRPCCall::stream` 或 `MyRPCCall::bidi` 返回，可以用 `wait()` 逐个阻塞读取。
+/// 调用普通函数时产出唯一的返回值。在结束之前 drop 时取消调用，服务端随即丢弃流
+pub struct CallStream<R> {
+    receiver: UnboundedReceiver<Response>,
+    format: Format,
+    done: bool,
+    /// 拦截器直接给出响应时为 None
+    queue: Option<Rc<Queue>>,
+    id: u32,
+    /// 取走之后还没有归还给服务端的额度
+    consumed: u32,
+    item: PhantomData<R>,
+}
+
+impl<R> CallStream<R> {
+    pub(crate) fn new(receiver: UnboundedReceiver<Response>, format: Format, queue: Option<Rc<Queue>>, id: u32) -> Self {
+        Self {
+            receiver,
+            format,
+            done: false,
+            queue,
+            id,
+            consumed: 0,
+            item: PhantomData,
+        }
+    }
+
+    fn consume(&mut self) {
+        self.consumed += 1;
+        if self.consumed >= DEFAULT_WINDOW / 2 {
+            if let Some(ref queue) = self.queue {
+                queue.window(self.id, self.consumed);
+            }
+            self.consumed = 0;
+        }
+    }
+}
+
+impl<R: DeserializeOwned> Stream for CallStream<R> {
+    type Item = R;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<R>, Error> {
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+        let response = match self.receiver.poll() {
+            Ok(Async::Ready(Some(response))) => response,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            // NET 层线程意外退出
+            _ => {
+                self.done = true;
+                return Err(Error::ConnectionFail);
+            }
+        };
+        self.done = response.is_end();
+        if !self.done {
+            self.consume();
+        }
+        match response.result {
+            Ok(_) if response.stream.is_some_and(|frame| frame.end) => Ok(Async::Ready(None)),
+            Ok(bytes) => self.format.deserialize(&bytes).map(|value| Async::Ready(Some(value))).map_err(|_| Error::ResultDeserializeFail),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl<R> Drop for CallStream<R> {
+    fn drop(&mut self) {
+        if let (false, Some(queue)) = (self.done, self.queue.as_ref()) {
+            queue.cancel(self.id);
+        }
+    }
+}
+
+/// 客户端在双向流式调用中发送消息的额度，由 NET 层线程按服务端的 `Control::Window` 增加
+pub(crate) struct Window {
+    /// 剩余额度，以及调用是否已经结束
+    state: Mutex<(u32, bool)>,
+    changed: Condvar,
+}
+
+impl Window {
+    pub fn new(credit: u32) -> Self {
+        Self {
+            state: Mutex::new((credit, false)),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn grant(&self, credit: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.0 = state.0.saturating_add(credit);
+        self.changed.notify_all();
+    }
+
+    pub fn close(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.changed.notify_all();
+    }
+
+    /// 等待并取得一个额度，调用已经结束时返回 `Error::ConnectionFail`
+    fn acquire(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        while state.0 == 0 && !state.1 {
+            state = self.changed.wait(state).unwrap();
+        }
+        if state.1 {
+            return Err(Error::ConnectionFail);
+        }
+        state.0 -= 1;
+        Ok(())
+    }
+}
+
+/// 双向流式调用中客户端发送消息的一端，由 `MyRPCCall::bidi` 返回。drop 或 `finish` 时告知服务端消息已经发完
+pub struct RequestSink {
+    id: u32,
+    name: String,
+    format: Format,
+    sequence: u32,
+    queue: Rc<Queue>,
+    window: Arc<Window>,
+}
+
+impl RequestSink {
+    pub(crate) fn new(request: &Request, format: Format, queue: Rc<Queue>, window: Arc<Window>) -> Self {
+        Self {
+            id: request.id,
+            name: request.name.clone(),
+            format,
+            sequence: 0,
+            queue,
+            window,
+        }
+    }
+
+    /// 服务端还没有取走的消息达到额度时阻塞。调用已经结束（服务端返回了结果或者连接断开）时
+    /// 返回 `Error::ConnectionFail`
+    pub fn send<T: Serialize>(&mut self, message: &T) -> Result<()> {
+        let bytes = Serializer::serialize(&self.format, message).map_err(|_| Error::ParamDeserializeFail)?;
+        self.window.acquire()?;
+        self.push(vec![bytes], false)
+    }
+
+    pub fn finish(self) {}
+
+    fn push(&mut self, params: Vec<Vec<u8>>, end: bool) -> Result<()> {
+        self.sequence += 1;
+        self.queue.message(Request {
+            id: self.id,
+            name: self.name.clone(),
+            params,
+            headers: Headers::new(),
+            one_way: false,
+            stream: Some(StreamFrame { sequence: self.sequence, end }),
+        })
+    }
+}
+
+impl Drop for RequestSink {
+    fn drop(&mut self) {
+        let _ = self.push(vec![], true);
+    }
+}
+
+/// 服务端在双向流式调用中收到、还没有交给函数的消息
+pub(crate) struct Incoming {
+    pub id: u32,
+    pending: VecDeque<Vec<u8>>,
+    /// 函数丢弃了消息流之后为 None，之后的消息直接丢弃
+    sender: Option<Sender<Vec<u8>>>,
+    /// 收到了客户端的结束帧
+    ended: bool,
+    /// 消息流是正常结束还是因为连接断开
+    completed: Arc<AtomicBool>,
+}
+
+impl Incoming {
+    pub fn new(id: u32) -> (Self, RequestStream) {
+        // 每次只交出一个消息，函数取走之后才继续，其余的消息留在 `pending` 中
+        let (sender, receiver) = mpsc::channel(0);
+        let completed = Arc::new(AtomicBool::new(false));
+        let messages = Messages {
+            receiver,
+            completed: Arc::clone(&completed),
+        };
+        let incoming = Self {
+            id,
+            pending: VecDeque::new(),
+            sender: Some(sender),
+            ended: false,
+            completed,
+        };
+        (incoming, Box::new(messages))
+    }
+
+    /// 还没有交给函数的消息超过客户端的额度时返回 false
+    pub fn receive(&mut self, request: Request) -> bool {
+        if request.stream.is_some_and(|frame| frame.end) {
+            self.ended = true;
+        } else {
+            self.pending.extend(request.params);
+        }
+        self.pending.len() <= DEFAULT_WINDOW as usize
+    }
+
+    /// 把消息交给函数，返回交出或丢弃的消息数，即可以归还给客户端的额度
+    pub fn forward(&mut self) -> u32 {
+        let mut forwarded = 0;
+        while !self.pending.is_empty() {
+            let ready = self.sender.as_mut().map(|sender| sender.poll_ready());
+            match ready {
+                Some(Ok(Async::NotReady)) => break,
+                Some(Ok(Async::Ready(()))) => {
+                    let message = self.pending.pop_front().unwrap();
+                    let _ = self.sender.as_mut().unwrap().try_send(message);
+                }
+                // 函数不再读取消息
+                Some(Err(_)) | None => {
+                    self.sender = None;
+                    self.pending.pop_front();
+                }
+            }
+            forwarded += 1;
+        }
+        if self.is_finished() {
+            self.completed.store(true, Ordering::SeqCst);
+            self.sender = None;
+        }
+        forwarded
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.ended && self.pending.is_empty()
+    }
+}
+
+struct Messages {
+    receiver: Receiver<Vec<u8>>,
+    completed: Arc<AtomicBool>,
+}
+
+impl Stream for Messages {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Vec<u8>>, Error> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(None)) | Err(()) if !self.completed.load(Ordering::SeqCst) => Err(Error::ConnectionFail),
+            Ok(Async::Ready(message)) => Ok(Async::Ready(message)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(()) => Ok(Async::Ready(None)),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use address::Address;
+use futures::Stream;
+use pubsub::Publisher;
+use server::{MyRPCServer, ShutdownHandle};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Handle;
+use transport::{Connecting, Incoming, Socket, TcpTransport, Transport};
+
+/// 在新线程中运行的服务端，drop 时停止服务端并等待线程退出
+pub struct TestServer {
+    shutdown: ShutdownHandle,
+    publisher: Publisher,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    pub fn publisher(&self) -> Publisher {
+        self.publisher.clone()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(thread) = self.thread.take() {
+            // 测试已经失败时不再重复 panic
+            if thread.join().is_err() && !thread::panicking() {
+                panic!("服务端线程 panic");
+            }
+        }
+    }
+}
+
+/// 服务端不能跨线程传递，在新线程中由 `setup` 创建
+pub fn spawn_test_server<F>(setup: F) -> TestServer where F: FnOnce() -> MyRPCServer + Send + 'static {
+    let (sender, receiver) = mpsc::channel();
+    let thread = thread::spawn(move || {
+        let mut myrpc = setup();
+        sender.send((myrpc.shutdown_handle(), myrpc.publisher())).unwrap();
+        myrpc.start_server();
+    });
+    let (shutdown, publisher) = receiver.recv().unwrap();
+    TestServer { shutdown, publisher, thread: Some(thread) }
+}
+
+/// 在系统分配的端口上监听，并报告实际的地址
+pub struct LocalTransport(pub mpsc::Sender<SocketAddr>);
+
+impl Transport for LocalTransport {
+    fn listen(&self, address: &Address, handle: &Handle) -> io::Result<Incoming> {
+        let listener = match *address {
+            Address::Tcp(ref addr) => TcpListener::bind(addr, handle)?,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "tcp only")),
+        };
+        self.0.send(listener.local_addr()?).unwrap();
+        Ok(Box::new(listener.incoming()
+            .map(|(socket, addr)| (Box::new(socket) as Box<dyn Socket>, Address::Tcp(addr)))))
+    }
+
+    fn connect(&self, address: &Address, handle: &Handle) -> Connecting {
+        TcpTransport.connect(address, handle)
+    }
+}
+
+/// 需要真实 TCP 连接的测试使用，`setup` 在监听 `127.0.0.1:0` 的服务端上注册函数，返回实际监听的地址
+pub fn spawn_tcp_server<F>(setup: F) -> (TestServer, SocketAddr) where F: FnOnce(MyRPCServer) -> MyRPCServer + Send + 'static {
+    let (sender, receiver) = mpsc::channel();
+    let server = spawn_test_server(move || {
+        setup(MyRPCServer::new("127.0.0.1:0".parse().unwrap()).transport(LocalTransport(sender)))
+    });
+    (server, receiver.recv().unwrap())
+}
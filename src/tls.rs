@@ -0,0 +1,344 @@
+use rustls::{ClientConfig, ClientConnection, ConnectionCommon, RootCertStore, ServerConfig, ServerConnection, SideData, StreamOwned};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls_pemfile;
+use x509_parser;
+use x509_parser::extensions::GeneralName;
+use context::Identity;
+use futures::{future, Async, Future, Poll, Stream};
+use tokio_core::reactor::Handle;
+use transport::{Connecting, Incoming, Socket, Transport};
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use address::Address;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// 服务端 TLS 配置：PEM 格式的证书链和私钥
+///
+/// 用 `client_auth` 指定 CA 后要求客户端提供证书（mTLS），证书中的身份通过
+/// `RequestContext::identity` 传给处理函数。
+///
+/// ```no_run
+/// use myrpc4rs::server::MyRPCServer;
+/// use myrpc4rs::tls::ServerTlsConfig;
+///
+/// let tls = ServerTlsConfig::new("server.crt", "server.key").unwrap()
+///     .client_auth("ca.crt").unwrap();
+/// let mut myrpc = MyRPCServer::new("127.0.0.1:6181".parse().unwrap()).tls(tls);
+/// ```
+#[derive(Clone)]
+pub struct ServerTlsConfig {
+    certs: Vec<CertificateDer<'static>>,
+    key: Arc<PrivateKeyDer<'static>>,
+    config: Arc<ServerConfig>,
+}
+
+impl ServerTlsConfig {
+    pub fn new<P: AsRef<Path>>(cert_file: P, key_file: P) -> io::Result<Self> {
+        Self::from_pem(&fs::read(cert_file)?, &fs::read(key_file)?)
+    }
+
+    pub fn from_pem(cert: &[u8], key: &[u8]) -> io::Result<Self> {
+        let (certs, key) = read_identity(cert, key)?;
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs.clone(), key.clone_key())
+            .map_err(invalid_data)?;
+        Ok(Self { certs, key: Arc::new(key), config: Arc::new(config) })
+    }
+
+    /// 要求客户端提供由 `ca_file` 中的 CA 签发的证书
+    pub fn client_auth<P: AsRef<Path>>(self, ca_file: P) -> io::Result<Self> {
+        self.client_auth_pem(&fs::read(ca_file)?)
+    }
+
+    pub fn client_auth_pem(mut self, ca: &[u8]) -> io::Result<Self> {
+        let verifier = WebPkiClientVerifier::builder(Arc::new(read_roots(ca)?))
+            .build()
+            .map_err(invalid_data)?;
+        let config = ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(self.certs.clone(), self.key.clone_key())
+            .map_err(invalid_data)?;
+        self.config = Arc::new(config);
+        Ok(self)
+    }
+
+    pub(crate) fn accept<S: Read + Write>(&self, socket: S) -> io::Result<TlsStream<ServerConnection, S>> {
+        let connection = ServerConnection::new(Arc::clone(&self.config)).map_err(invalid_data)?;
+        Ok(TlsStream::new(connection, socket))
+    }
+}
+
+/// 客户端 TLS 配置：信任的 CA 证书（PEM 格式）
+///
+/// 默认用节点的 IP 地址（Unix 域套接字和进程内连接为 `localhost`）校验服务端证书，证书只包含域名时用 `server_name` 指定。
+/// 服务端要求 mTLS 时用 `identity` 提供客户端证书。
+///
+/// ```no_run
+/// use myrpc4rs::client::MyRPCClientBuilder;
+/// use myrpc4rs::tls::ClientTlsConfig;
+///
+/// let tls = ClientTlsConfig::new("ca.crt").unwrap()
+///     .server_name("path.example.com")
+///     .identity("client.crt", "client.key").unwrap();
+/// let mut client = MyRPCClientBuilder::new(vec!["127.0.0.1:6181".parse().unwrap()])
+///     .tls(tls)
+///     .build();
+/// ```
+#[derive(Clone)]
+pub struct ClientTlsConfig {
+    roots: Arc<RootCertStore>,
+    config: Arc<ClientConfig>,
+    server_name: Option<String>,
+}
+
+impl ClientTlsConfig {
+    pub fn new<P: AsRef<Path>>(ca_file: P) -> io::Result<Self> {
+        Self::from_pem(&fs::read(ca_file)?)
+    }
+
+    pub fn from_pem(ca: &[u8]) -> io::Result<Self> {
+        let roots = Arc::new(read_roots(ca)?);
+        let config = ClientConfig::builder()
+            .with_root_certificates(Arc::clone(&roots))
+            .with_no_client_auth();
+        Ok(Self { roots, config: Arc::new(config), server_name: None })
+    }
+
+    pub fn server_name(mut self, server_name: &str) -> Self {
+        self.server_name = Some(String::from(server_name));
+        self
+    }
+
+    /// 客户端证书和私钥
+    pub fn identity<P: AsRef<Path>>(self, cert_file: P, key_file: P) -> io::Result<Self> {
+        self.identity_pem(&fs::read(cert_file)?, &fs::read(key_file)?)
+    }
+
+    pub fn identity_pem(mut self, cert: &[u8], key: &[u8]) -> io::Result<Self> {
+        let (certs, key) = read_identity(cert, key)?;
+        let config = ClientConfig::builder()
+            .with_root_certificates(Arc::clone(&self.roots))
+            .with_client_auth_cert(certs, key)
+            .map_err(invalid_data)?;
+        self.config = Arc::new(config);
+        Ok(self)
+    }
+
+    pub(crate) fn connect<S: Read + Write>(&self, addr: &Address, socket: S) -> io::Result<TlsStream<ClientConnection, S>> {
+        let server_name = match self.server_name {
+            Some(ref name) => ServerName::try_from(name.clone())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?,
+            None => match *addr {
+                Address::Tcp(ref addr) => ServerName::from(addr.ip()),
+                Address::Unix(_) | Address::Memory(_) => ServerName::try_from("localhost").unwrap(),
+            },
+        };
+        let connection = ClientConnection::new(Arc::clone(&self.config), server_name).map_err(invalid_data)?;
+        Ok(TlsStream::new(connection, socket))
+    }
+}
+
+fn read_roots(ca: &[u8]) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut BufReader::new(ca)) {
+        roots.add(cert?).map_err(invalid_data)?;
+    }
+    if roots.is_empty() {
+        return Err(invalid_data("no CA certificate found"));
+    }
+    Ok(roots)
+}
+
+fn read_identity(cert: &[u8], key: &[u8]) -> io::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(cert)).collect::<io::Result<Vec<_>>>()?;
+    match rustls_pemfile::private_key(&mut BufReader::new(key))? {
+        Some(key) => Ok((certs, key)),
+        None => Err(invalid_data("no private key found")),
+    }
+}
+
+/// 从对端证书中取出主题和 SAN
+fn identity(cert: &CertificateDer) -> Option<Identity> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+    let mut sans = Vec::new();
+    if let Ok(Some(extension)) = cert.subject_alternative_name() {
+        for name in &extension.value.general_names {
+            match *name {
+                GeneralName::DNSName(name) | GeneralName::RFC822Name(name) | GeneralName::URI(name) => {
+                    sans.push(String::from(name));
+                }
+                GeneralName::IPAddress(bytes) if bytes.len() == 4 => {
+                    sans.push(Ipv4Addr::from([bytes[0], bytes[1], bytes[2], bytes[3]]).to_string());
+                }
+                GeneralName::IPAddress(bytes) if bytes.len() == 16 => {
+                    let mut octets = [0u8; 16];
+                    octets.copy_from_slice(bytes);
+                    sans.push(Ipv6Addr::from(octets).to_string());
+                }
+                _ => {}
+            }
+        }
+    }
+    Some(Identity::new(&cert.subject().to_string(), sans))
+}
+
+/// 在非阻塞的底层连接上进行 TLS 读写，握手在第一次读写时完成
+pub struct TlsStream<C, S: Read + Write> {
+    stream: StreamOwned<C, S>,
+}
+
+impl<C, S, D> TlsStream<C, S>
+    where C: DerefMut + Deref<Target = ConnectionCommon<D>>, S: Read + Write, D: SideData {
+    fn new(connection: C, socket: S) -> Self {
+        Self { stream: StreamOwned::new(connection, socket) }
+    }
+
+    /// 握手完成后对端证书中的身份
+    pub fn peer_identity(&self) -> Option<Identity> {
+        self.stream.conn.peer_certificates()?.first().and_then(identity)
+    }
+}
+
+impl<C, S, D> Read for TlsStream<C, S>
+    where C: DerefMut + Deref<Target = ConnectionCommon<D>>, S: Read + Write, D: SideData {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl<C, S, D> Write for TlsStream<C, S>
+    where C: DerefMut + Deref<Target = ConnectionCommon<D>>, S: Read + Write, D: SideData {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<C, S, D> AsyncRead for TlsStream<C, S>
+    where C: DerefMut + Deref<Target = ConnectionCommon<D>>, S: AsyncRead + AsyncWrite, D: SideData {}
+
+impl<C, S, D> AsyncWrite for TlsStream<C, S>
+    where C: DerefMut + Deref<Target = ConnectionCommon<D>>, S: AsyncRead + AsyncWrite, D: SideData {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.stream.conn.send_close_notify();
+        match self.stream.flush() {
+            Ok(()) => self.stream.sock.shutdown(),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<C, S, D> Socket for TlsStream<C, S>
+    where C: DerefMut + Deref<Target = ConnectionCommon<D>>, S: AsyncRead + AsyncWrite, D: SideData {
+    fn peer_identity(&self) -> Option<Identity> {
+        TlsStream::peer_identity(self)
+    }
+}
+
+/// 在另一个传输层之上加密：服务端对接受的连接使用 `server` 配置，客户端连接使用 `client` 配置
+///
+/// ```no_run
+/// use myrpc4rs::transport::TcpTransport;
+/// use myrpc4rs::tls::{ClientTlsConfig, TlsTransport};
+/// use myrpc4rs::client::MyRPCClientBuilder;
+///
+/// let tls = ClientTlsConfig::new("ca.crt").unwrap();
+/// let mut client = MyRPCClientBuilder::new(vec!["127.0.0.1:6181".parse().unwrap()])
+///     .transport(TlsTransport::new(TcpTransport).client(tls))
+///     .build();
+/// ```
+pub struct TlsTransport<T> {
+    inner: T,
+    server: Option<ServerTlsConfig>,
+    client: Option<ClientTlsConfig>,
+}
+
+impl<T: Transport> TlsTransport<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            server: None,
+            client: None,
+        }
+    }
+
+    pub fn server(mut self, server: ServerTlsConfig) -> Self {
+        self.server = Some(server);
+        self
+    }
+
+    pub fn client(mut self, client: ClientTlsConfig) -> Self {
+        self.client = Some(client);
+        self
+    }
+}
+
+impl<T: Transport> Transport for TlsTransport<T> {
+    fn listen(&self, address: &Address, handle: &Handle) -> io::Result<Incoming> {
+        let config = match self.server {
+            Some(ref config) => config.clone(),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "server TLS config is missing")),
+        };
+        let incoming = self.inner.listen(address, handle)?
+            .filter_map(move |(socket, addr)| match config.accept(socket) {
+                Ok(socket) => Some((Box::new(socket) as Box<dyn Socket>, addr)),
+                Err(e) => {
+                    println!("ERR:连接 {} 初始化失败: {}", addr, e);
+                    None
+                }
+            });
+        Ok(Box::new(incoming))
+    }
+
+    fn connect(&self, address: &Address, handle: &Handle) -> Connecting {
+        let config = match self.client {
+            Some(ref config) => config.clone(),
+            None => return Box::new(future::err(io::Error::new(io::ErrorKind::InvalidInput, "client TLS config is missing"))),
+        };
+        let address = address.clone();
+        Box::new(self.inner.connect(&address, handle).and_then(move |socket| {
+            config.connect(&address, socket).map(|socket| Box::new(socket) as Box<dyn Socket>)
+        }))
+    }
+}
+
+fn invalid_data<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use rcgen::{CertifiedKey, generate_simple_self_signed};
+    use tls::{ClientTlsConfig, ServerTlsConfig};
+
+    /// 生成包含 localhost 和 127.0.0.1 的自签名证书，返回 (证书, 私钥)
+    pub fn self_signed() -> (String, String) {
+        self_signed_for(&["localhost", "127.0.0.1"])
+    }
+
+    pub fn self_signed_for(names: &[&str]) -> (String, String) {
+        let names = names.iter().map(|name| String::from(*name)).collect::<Vec<String>>();
+        let CertifiedKey { cert, key_pair } = generate_simple_self_signed(names).unwrap();
+        (cert.pem(), key_pair.serialize_pem())
+    }
+
+    #[test]
+    fn config_test() {
+        let (cert, key) = self_signed();
+        assert!(ServerTlsConfig::from_pem(cert.as_bytes(), key.as_bytes()).is_ok());
+        assert!(ServerTlsConfig::from_pem(cert.as_bytes(), b"").is_err());
+        assert!(ClientTlsConfig::from_pem(cert.as_bytes()).is_ok());
+        assert!(ClientTlsConfig::from_pem(b"").is_err());
+    }
+}
@@ -0,0 +1,180 @@
+use address::Address;
+use context::Identity;
+use serialization::Format;
+use futures::{future, Future, Stream};
+use memory::{self, MemoryStream};
+use std::io;
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use tokio_uds::{UnixListener, UnixStream};
+
+/// 已建立的连接，分包、序列化和调度都在它之上进行
+pub trait Socket: AsyncRead + AsyncWrite {
+    /// 对端通过 mTLS 证明的身份
+    fn peer_identity(&self) -> Option<Identity> {
+        None
+    }
+
+    /// 连接上请求和响应的序列化格式
+    fn format(&self) -> Format {
+        Format::Bincode
+    }
+}
+
+impl Socket for TcpStream {}
+
+#[cfg(unix)]
+impl Socket for UnixStream {}
+
+impl Socket for MemoryStream {}
+
+impl Socket for Box<dyn Socket> {
+    fn peer_identity(&self) -> Option<Identity> {
+        (**self).peer_identity()
+    }
+
+    fn format(&self) -> Format {
+        (**self).format()
+    }
+}
+
+/// 监听地址上接受的连接及对端地址
+pub type Incoming = Box<dyn Stream<Item = (Box<dyn Socket>, Address), Error = io::Error>>;
+pub type Connecting = Box<dyn Future<Item = Box<dyn Socket>, Error = io::Error>>;
+
+/// 传输层：服务端在地址上监听并接受连接，客户端连接到地址。
+/// 返回的 Future 和 Stream 在调用方线程的 reactor（`handle`）上运行。
+///
+/// 可以实现这个 trait 接入自定义的传输方式，通过 `MyRPCServer::transport`
+/// 和 `MyRPCClientBuilder::transport` 使用。
+pub trait Transport {
+    fn listen(&self, address: &Address, handle: &Handle) -> io::Result<Incoming>;
+    fn connect(&self, address: &Address, handle: &Handle) -> Connecting;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn listen(&self, address: &Address, handle: &Handle) -> io::Result<Incoming> {
+        (**self).listen(address, handle)
+    }
+
+    fn connect(&self, address: &Address, handle: &Handle) -> Connecting {
+        (**self).connect(address, handle)
+    }
+}
+
+fn unsupported(transport: &str, address: &Address) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{} transport does not support {}", transport, address))
+}
+
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    fn listen(&self, address: &Address, handle: &Handle) -> io::Result<Incoming> {
+        let addr = match *address {
+            Address::Tcp(ref addr) => addr,
+            _ => return Err(unsupported("tcp", address)),
+        };
+        let incoming = TcpListener::bind(addr, handle)?.incoming()
+            .map(|(socket, addr)| (Box::new(socket) as Box<dyn Socket>, Address::Tcp(addr)));
+        Ok(Box::new(incoming))
+    }
+
+    fn connect(&self, address: &Address, handle: &Handle) -> Connecting {
+        match *address {
+            Address::Tcp(ref addr) => Box::new(TcpStream::connect(addr, handle)
+                .map(|socket| Box::new(socket) as Box<dyn Socket>)),
+            _ => Box::new(future::err(unsupported("tcp", address))),
+        }
+    }
+}
+
+#[cfg(unix)]
+pub struct UnixTransport;
+
+#[cfg(unix)]
+impl Transport for UnixTransport {
+    fn listen(&self, address: &Address, handle: &Handle) -> io::Result<Incoming> {
+        let path = match *address {
+            Address::Unix(ref path) => path,
+            _ => return Err(unsupported("unix", address)),
+        };
+        // 清理上次运行留下的套接字文件
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        let incoming = UnixListener::bind(path, handle)?.incoming()
+            .map(|(socket, addr)| {
+                let path = addr.as_pathname().map(Path::to_path_buf).unwrap_or_default();
+                (Box::new(socket) as Box<dyn Socket>, Address::Unix(path))
+            });
+        Ok(Box::new(incoming))
+    }
+
+    fn connect(&self, address: &Address, handle: &Handle) -> Connecting {
+        match *address {
+            Address::Unix(ref path) => Box::new(future::result(UnixStream::connect(path, handle)
+                .map(|socket| Box::new(socket) as Box<dyn Socket>))),
+            _ => Box::new(future::err(unsupported("unix", address))),
+        }
+    }
+}
+
+/// 同一进程内的连接，不经过套接字
+pub struct MemoryTransport;
+
+impl Transport for MemoryTransport {
+    fn listen(&self, address: &Address, _handle: &Handle) -> io::Result<Incoming> {
+        let name = match *address {
+            Address::Memory(ref name) => name,
+            _ => return Err(unsupported("memory", address)),
+        };
+        let peer = address.clone();
+        let incoming = memory::listen(name)
+            .map(move |socket| (Box::new(socket) as Box<dyn Socket>, peer.clone()))
+            .map_err(|_| io::Error::other("memory listener closed"));
+        Ok(Box::new(incoming))
+    }
+
+    fn connect(&self, address: &Address, _handle: &Handle) -> Connecting {
+        match *address {
+            Address::Memory(ref name) => Box::new(future::result(memory::connect(name)
+                .map(|socket| Box::new(socket) as Box<dyn Socket>))),
+            _ => Box::new(future::err(unsupported("memory", address))),
+        }
+    }
+}
+
+/// 按地址类型选择 TCP、Unix 域套接字或进程内传输，服务端和客户端默认使用
+pub struct DefaultTransport;
+
+impl Transport for DefaultTransport {
+    fn listen(&self, address: &Address, handle: &Handle) -> io::Result<Incoming> {
+        match *address {
+            Address::Tcp(_) => TcpTransport.listen(address, handle),
+            #[cfg(unix)]
+            Address::Unix(_) => UnixTransport.listen(address, handle),
+            #[cfg(not(unix))]
+            Address::Unix(_) => Err(unsupported("default", address)),
+            Address::Memory(_) => MemoryTransport.listen(address, handle),
+        }
+    }
+
+    fn connect(&self, address: &Address, handle: &Handle) -> Connecting {
+        match *address {
+            Address::Tcp(_) => TcpTransport.connect(address, handle),
+            #[cfg(unix)]
+            Address::Unix(_) => UnixTransport.connect(address, handle),
+            #[cfg(not(unix))]
+            Address::Unix(_) => Box::new(future::err(unsupported("default", address))),
+            Address::Memory(_) => MemoryTransport.connect(address, handle),
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use address::Address;
+use byteorder::{BigEndian, ByteOrder};
+use context::Identity;
+use futures::stream::FuturesUnordered;
+use futures::{future, Async, Future, Poll, Stream};
+use serialization::Format;
+use std::cell::Cell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+use transport::{Connecting, Incoming, Socket, Transport};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::handshake::{HandshakeError, HandshakeRole, MidHandshake};
+use tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL;
+use tungstenite::http::{HeaderValue, StatusCode};
+use tungstenite::{self, Message, WebSocket};
+
+/// 客户端通过 `Sec-WebSocket-Protocol` 选择序列化格式，没有指定时为 JSON
+pub const BINCODE_PROTOCOL: &str = "myrpc.bincode";
+pub const JSON_PROTOCOL: &str = "myrpc.json";
+
+/// 一条 WebSocket 消息对应一帧：分包层写入的 `[长度][内容]` 以二进制消息发出，
+/// 收到的消息（二进制或文本）再加上长度前缀交给分包层
+pub struct WsStream<S: Read + Write> {
+    socket: WebSocket<S>,
+    format: Format,
+    /// 已收到、尚未被读走的帧
+    read_buffer: Vec<u8>,
+    /// 已写入、尚未凑成完整帧的字节
+    write_buffer: Vec<u8>,
+}
+
+impl<S: Read + Write> WsStream<S> {
+    fn new(socket: WebSocket<S>, format: Format) -> Self {
+        Self {
+            socket,
+            format,
+            read_buffer: Vec::new(),
+            write_buffer: Vec::new(),
+        }
+    }
+}
+
+impl<S: Read + Write> Read for WsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read_buffer.is_empty() {
+            let frame = match self.socket.read() {
+                Ok(Message::Binary(data)) => data,
+                Ok(Message::Text(text)) => text.into_bytes(),
+                Ok(_) => continue,
+                Err(tungstenite::Error::ConnectionClosed) | Err(tungstenite::Error::AlreadyClosed) => return Ok(0),
+                Err(e) => return Err(io_error(e)),
+            };
+            let mut length = [0u8; 4];
+            BigEndian::write_u32(&mut length, frame.len() as u32);
+            self.read_buffer.extend_from_slice(&length);
+            self.read_buffer.extend_from_slice(&frame);
+        }
+        let n = ::std::cmp::min(buf.len(), self.read_buffer.len());
+        buf[..n].copy_from_slice(&self.read_buffer[..n]);
+        self.read_buffer.drain(..n);
+        Ok(n)
+    }
+}
+
+impl<S: Read + Write> Write for WsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_buffer.extend_from_slice(buf);
+        while self.write_buffer.len() >= 4 {
+            let length = BigEndian::read_u32(&self.write_buffer[..4]) as usize;
+            if self.write_buffer.len() < 4 + length {
+                break;
+            }
+            let frame = self.write_buffer.drain(..4 + length).skip(4).collect();
+            // WouldBlock 时消息已经进入 WebSocket 的发送缓冲，由 flush 继续发送
+            match self.socket.write(Message::Binary(frame)) {
+                Ok(()) => {}
+                Err(tungstenite::Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush().map_err(io_error)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncRead for WsStream<S> {}
+
+impl<S: AsyncRead + AsyncWrite> AsyncWrite for WsStream<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self.socket.close(None) {
+            Ok(()) | Err(tungstenite::Error::ConnectionClosed) => Ok(Async::Ready(())),
+            Err(tungstenite::Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+            Err(e) => Err(io_error(e)),
+        }
+    }
+}
+
+impl<S: Socket> Socket for WsStream<S> {
+    fn peer_identity(&self) -> Option<Identity> {
+        self.socket.get_ref().peer_identity()
+    }
+
+    fn format(&self) -> Format {
+        self.format
+    }
+}
+
+/// 在另一个传输层之上使用 WebSocket，浏览器可以直接调用服务端的函数
+///
+/// 服务端按客户端请求的子协议选择格式：`myrpc.bincode` 或 `myrpc.json`，没有指定时为 JSON。
+/// JSON 请求的参数直接是 JSON 值：`{"id":1,"name":"add","params":[1,2]}`，
+/// 响应为 `{"id":1,"name":"add","result":{"Ok":3}}`。客户端总是使用 bincode。
+///
+/// ```no_run
+/// use myrpc4rs::server::MyRPCServer;
+/// use myrpc4rs::transport::DefaultTransport;
+/// use myrpc4rs::ws::WsTransport;
+///
+/// // TCP 上的 6181 端口供 Rust 客户端使用，8181 端口供网页使用
+/// let myrpc = MyRPCServer::new("127.0.0.1:6181".parse().unwrap())
+///     .listen("127.0.0.1:8181".parse().unwrap(), WsTransport::new(DefaultTransport).path("/rpc"));
+/// ```
+pub struct WsTransport<T> {
+    inner: T,
+    path: String,
+}
+
+impl<T: Transport> WsTransport<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            path: String::from("/"),
+        }
+    }
+
+    /// 握手请求的路径，默认为 `/`，服务端拒绝其他路径
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = String::from(path);
+        self
+    }
+}
+
+impl<T: Transport> Transport for WsTransport<T> {
+    fn listen(&self, address: &Address, handle: &Handle) -> io::Result<Incoming> {
+        Ok(Box::new(Accepting {
+            incoming: Some(self.inner.listen(address, handle)?),
+            handshakes: FuturesUnordered::new(),
+            path: self.path.clone(),
+        }))
+    }
+
+    fn connect(&self, address: &Address, handle: &Handle) -> Connecting {
+        let host = match *address {
+            Address::Tcp(ref addr) => addr.to_string(),
+            Address::Unix(_) | Address::Memory(_) => String::from("localhost"),
+        };
+        let mut request = match format!("ws://{}{}", host, self.path).into_client_request() {
+            Ok(request) => request,
+            Err(e) => return Box::new(future::err(io_error(e))),
+        };
+        request.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(BINCODE_PROTOCOL));
+        Box::new(self.inner.connect(address, handle).and_then(move |socket| {
+            Handshaking::new(move || Step::from(tungstenite::client(request, socket)))
+                .map(|(socket, _)| Box::new(WsStream::new(socket, Format::Bincode)) as Box<dyn Socket>)
+        }))
+    }
+}
+
+type Accepted = Box<dyn Future<Item = Option<(Box<dyn Socket>, Address)>, Error = io::Error>>;
+
+/// 同时进行多个连接的握手，握手慢的客户端不会阻塞其他连接
+struct Accepting {
+    incoming: Option<Incoming>,
+    handshakes: FuturesUnordered<Accepted>,
+    path: String,
+}
+
+impl Accepting {
+    // 回调的返回类型由 tungstenite 决定
+    #[allow(clippy::result_large_err)]
+    fn accept(&self, socket: Box<dyn Socket>, addr: Address) -> Accepted {
+        let path = self.path.clone();
+        let format = Rc::new(Cell::new(Format::Json));
+        let selected = Rc::clone(&format);
+        let callback = move |request: &Request, mut response: Response| -> Result<Response, ErrorResponse> {
+            if request.uri().path() != path {
+                let mut response = ErrorResponse::new(None);
+                *response.status_mut() = StatusCode::NOT_FOUND;
+                return Err(response);
+            }
+            let protocols = request.headers().get_all(SEC_WEBSOCKET_PROTOCOL).iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .map(str::trim)
+                .collect::<Vec<&str>>();
+            if let Some(protocol) = protocols.into_iter().find(|p| *p == BINCODE_PROTOCOL || *p == JSON_PROTOCOL) {
+                selected.set(if protocol == BINCODE_PROTOCOL { Format::Bincode } else { Format::Json });
+                response.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_str(protocol).unwrap());
+            }
+            Ok(response)
+        };
+        Box::new(Handshaking::new(move || Step::from(tungstenite::accept_hdr(socket, callback)))
+            .then(move |result| match result {
+                Ok(socket) => Ok(Some((Box::new(WsStream::new(socket, format.get())) as Box<dyn Socket>, addr))),
+                Err(e) => {
+                    println!("ERR:连接 {} WebSocket 握手失败: {}", addr, e);
+                    Ok(None)
+                }
+            }))
+    }
+}
+
+impl Stream for Accepting {
+    type Item = (Box<dyn Socket>, Address);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        while let Some(Async::Ready(accepted)) = match self.incoming {
+            Some(ref mut incoming) => Some(incoming.poll()?),
+            None => None,
+        } {
+            match accepted {
+                Some((socket, addr)) => {
+                    let handshake = self.accept(socket, addr);
+                    self.handshakes.push(handshake);
+                }
+                None => self.incoming = None,
+            }
+        }
+        loop {
+            match self.handshakes.poll()? {
+                Async::Ready(Some(Some(accepted))) => return Ok(Async::Ready(Some(accepted))),
+                Async::Ready(Some(None)) => continue,
+                Async::Ready(None) if self.incoming.is_none() => return Ok(Async::Ready(None)),
+                _ => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+/// 握手推进一步的结果
+enum Step<H: HandshakeRole> {
+    Done(H::FinalResult),
+    Blocked(MidHandshake<H>),
+    Failed(io::Error),
+}
+
+impl<H: HandshakeRole> From<Result<H::FinalResult, HandshakeError<H>>> for Step<H> {
+    fn from(result: Result<H::FinalResult, HandshakeError<H>>) -> Self {
+        match result {
+            Ok(done) => Step::Done(done),
+            Err(HandshakeError::Interrupted(mid)) => Step::Blocked(mid),
+            Err(HandshakeError::Failure(e)) => Step::Failed(io_error(e)),
+        }
+    }
+}
+
+/// 非阻塞地推进握手，底层连接返回 WouldBlock 时已经在 reactor 上登记
+struct Handshaking<H: HandshakeRole> {
+    start: Option<Box<dyn FnOnce() -> Step<H>>>,
+    mid: Option<MidHandshake<H>>,
+}
+
+impl<H: HandshakeRole> Handshaking<H> {
+    fn new<F: 'static + FnOnce() -> Step<H>>(start: F) -> Self {
+        Self {
+            start: Some(Box::new(start)),
+            mid: None,
+        }
+    }
+}
+
+impl<H: HandshakeRole> Future for Handshaking<H> {
+    type Item = H::FinalResult;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let step = match (self.mid.take(), self.start.take()) {
+            (Some(mid), _) => Step::from(mid.handshake()),
+            (None, Some(start)) => start(),
+            (None, None) => panic!("poll after handshake completed"),
+        };
+        match step {
+            Step::Done(done) => Ok(Async::Ready(done)),
+            Step::Blocked(mid) => {
+                self.mid = Some(mid);
+                Ok(Async::NotReady)
+            }
+            Step::Failed(e) => Err(e),
+        }
+    }
+}
+
+fn io_error(error: tungstenite::Error) -> io::Error {
+    match error {
+        tungstenite::Error::Io(e) => e,
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => io::ErrorKind::BrokenPipe.into(),
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}